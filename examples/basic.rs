@@ -1,9 +1,14 @@
 use std::collections::HashMap;
+use std::time::Duration;
 
 use env_logger::Env;
-use gossip::{GossipConfig, start, tailscale, util};
+use gossip::message::GossipMessage;
+use gossip::{GossipConfig, Handlers, Node, start, tailscale, util};
 use log::info;
 
+/// Application message type for chat messages
+const CHAT: u16 = 1;
+
 #[tokio::main]
 async fn main() {
     env_logger::init_from_env(Env::default().default_filter_or("info"));
@@ -35,7 +40,37 @@ async fn main() {
     gossip_config.ip_address = ip.to_string();
     gossip_config.node_name = ts.id.clone();
 
-    start(gossip_config, Box::new(ts), seed_peers)
+    let mut handlers = Handlers::new();
+    handlers.register(CHAT, |from: Node, msg: GossipMessage| async move {
+        match msg.decode::<String>() {
+            Ok(text) => info!("chat from {}: {}", from.id, text),
+            Err(e) => info!("bad chat from {}: {}", from.id, e),
+        }
+    });
+
+    let discovery = ts.discovery();
+    let mut handle = start(gossip_config, Box::new(ts), seed_peers, handlers)
         .await
         .unwrap();
+    handle.discover(discovery);
+
+    let mut events = handle.events();
+    let mut ticker = tokio::time::interval(Duration::from_secs(5));
+    loop {
+        tokio::select! {
+            Ok(event) = events.recv() => {
+                info!("membership: {:?}", event);
+            }
+            _ = ticker.tick() => {
+                let text = format!("hello from {}", handle.local_node().id);
+                if let Err(e) = handle.broadcast(CHAT, &text).await {
+                    info!("broadcast failed: {}", e);
+                }
+                info!("peers: {}", handle.peers().await.len());
+            }
+            _ = tokio::signal::ctrl_c() => break,
+        }
+    }
+
+    handle.shutdown().await.unwrap();
 }
@@ -0,0 +1,53 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+use env_logger::Env;
+use gossip::{GossipConfig, Handlers, Node, start, udp::UdpTransport, util};
+use log::info;
+
+/// Run a node over plain UDP on localhost.
+///
+///   cargo run --example udp -- 42069
+///   cargo run --example udp -- 42070 127.0.0.1:42069
+#[tokio::main]
+async fn main() {
+    env_logger::init_from_env(Env::default().default_filter_or("info"));
+
+    let mut args = std::env::args().skip(1);
+    let port = args.next().map(|p| p.parse().unwrap()).unwrap_or(42069);
+
+    // name nodes after their address so seeds can be given by address
+    let gossip_config = GossipConfig {
+        gossip_port: port,
+        node_name: format!("127.0.0.1:{}", port),
+        ..GossipConfig::default()
+    };
+
+    let seed_peers = args
+        .map(|addr| {
+            let addr = addr.parse::<SocketAddr>().unwrap();
+            let id = util::hash_node_name(&addr.to_string());
+            (id, Node::new(id, addr))
+        })
+        .collect::<HashMap<_, _>>();
+
+    let transport = UdpTransport::bind(&gossip_config).await.unwrap();
+    let handle = start(
+        gossip_config,
+        Box::new(transport),
+        seed_peers,
+        Handlers::new(),
+    )
+    .await
+    .unwrap();
+
+    let mut events = handle.events();
+    loop {
+        tokio::select! {
+            Ok(event) = events.recv() => info!("membership: {:?}", event),
+            _ = tokio::signal::ctrl_c() => break,
+        }
+    }
+
+    handle.shutdown().await.unwrap();
+}
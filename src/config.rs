@@ -1,5 +1,10 @@
+use std::collections::{BTreeMap, BTreeSet};
 use std::time::Duration;
 
+use ed25519_dalek::SigningKey;
+
+use crate::keyring::Keyring;
+
 #[derive(Debug, Clone)]
 pub struct GossipConfig {
     /// Time between heartbeats
@@ -20,6 +25,34 @@ pub struct GossipConfig {
     pub node_name: String,
     /// Message TTL
     pub message_ttl: u8,
+    /// Time between failure detector probes
+    pub probe_interval: Duration,
+    /// Time to wait for an ack before probing indirectly
+    pub probe_timeout: Duration,
+    /// Number of peers asked to probe a node indirectly
+    pub indirect_probes: usize,
+    /// Time a suspect node has to refute before it is marked offline
+    pub suspicion_timeout: Duration,
+    /// Largest datagram sent or received, in bytes
+    pub max_datagram_size: usize,
+    /// Largest application payload sent or reassembled, in bytes
+    pub max_message_size: usize,
+    /// Time allowed for every fragment of a message to arrive
+    pub reassembly_timeout: Duration,
+    /// Cluster keys; gossip is sent unencrypted when unset
+    pub keyring: Option<Keyring>,
+    /// Time between polls of a discovery source
+    pub discovery_interval: Duration,
+    /// Key/value pairs advertised in the node's record
+    pub metadata: BTreeMap<String, String>,
+    /// Tags advertised in the node's record
+    pub tags: BTreeSet<String>,
+    /// Time deleted keys are remembered, so older writes still
+    /// in flight can't bring them back
+    pub tombstone_ttl: Duration,
+    /// Ed25519 identity the node signs its messages with; a fresh one
+    /// is generated at start when unset
+    pub signing_key: Option<SigningKey>,
 }
 
 impl Default for GossipConfig {
@@ -29,6 +62,19 @@ impl Default for GossipConfig {
     /// offline_timeout: 10s
     /// fanout: 4
     /// message_ttl: 3
+    /// probe_interval: 1s
+    /// probe_timeout: 500ms
+    /// indirect_probes: 3
+    /// suspicion_timeout: 5s
+    /// max_datagram_size: 1024
+    /// max_message_size: 64 KiB
+    /// reassembly_timeout: 10s
+    /// discovery_interval: 30s
+    /// metadata: empty
+    /// tags: empty
+    /// keyring: none
+    /// tombstone_ttl: 1h
+    /// signing_key: generated
     fn default() -> Self {
         GossipConfig {
             gossip_port: 42069,
@@ -40,6 +86,19 @@ impl Default for GossipConfig {
             ip_address: "127.0.0.1".to_string(),
             node_name: "".to_string(),
             message_ttl: 3,
+            probe_interval: Duration::from_secs(1),
+            probe_timeout: Duration::from_millis(500),
+            indirect_probes: 3,
+            suspicion_timeout: Duration::from_secs(5),
+            max_datagram_size: 1024,
+            max_message_size: 64 * 1024,
+            reassembly_timeout: Duration::from_secs(10),
+            discovery_interval: Duration::from_secs(30),
+            metadata: BTreeMap::new(),
+            tags: BTreeSet::new(),
+            keyring: None,
+            tombstone_ttl: Duration::from_secs(60 * 60),
+            signing_key: None,
         }
     }
 }
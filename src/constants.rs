@@ -1 +1,52 @@
-pub const MAX_PAYLOAD_SIZE: usize = 1024;
+use std::time::Duration;
+
+/// Wire format version carried by every message:
+///
+/// - 1: the original format
+/// - 2: node IDs grew from 32 to 128 bits
+/// - 3: messages gained a sequence number
+/// - 4: the message type became a `MessageKind` enum
+/// - 5: large messages are split into fragments
+/// - 6: messages and node records are signed
+/// - 7: node records gained metadata and tags
+/// - 8: replicated key/value entries
+/// - 9: leave messages gained an incarnation
+/// - 10: direct requests and responses
+/// - 11: node records gained topics, and publications were added
+pub const PROTOCOL_VERSION: u8 = 11;
+
+/// Bytes of each datagram reserved for message, fragment and update
+/// headers; the rest of `max_datagram_size` carries payload.
+pub const MESSAGE_OVERHEAD: usize = 256;
+
+/// Smallest `max_datagram_size` a node can be started with.
+pub const MIN_DATAGRAM_SIZE: usize = 512;
+
+/// Largest encoded size of a node's metadata and tags, so its signed
+/// record always fits in an encrypted `update` message of
+/// `MIN_DATAGRAM_SIZE` bytes. Topics aren't counted here; they get
+/// whatever else of the node's own datagram the record can fill.
+pub const MAX_METADATA_SIZE: usize = 128;
+
+/// Upper bound on the encoded size of a signed node record, less its
+/// metadata, tags and topics.
+pub const NODE_RECORD_OVERHEAD: usize = 176;
+
+/// Largest frame accepted on a stream: a push-pull sync snapshot or
+/// a message too large for a datagram.
+pub const MAX_FRAME_SIZE: usize = 4 * 1024 * 1024;
+
+/// Time allowed for an exchange over a stream to complete.
+pub const STREAM_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Number of partially received messages buffered for reassembly.
+pub const REASSEMBLY_CAPACITY: usize = 64;
+
+/// Number of message IDs remembered for deduplication.
+pub const SEEN_CACHE_CAPACITY: usize = 8192;
+
+/// How long a message ID is remembered for deduplication.
+pub const SEEN_CACHE_WINDOW: Duration = Duration::from_secs(60);
+
+/// Number of application messages buffered per subscriber.
+pub const SUBSCRIBER_CAPACITY: usize = 256;
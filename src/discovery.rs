@@ -0,0 +1,259 @@
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use async_trait::async_trait;
+use hickory_resolver::TokioAsyncResolver;
+use hickory_resolver::config::{
+    NameServerConfig, Protocol, ResolverConfig, ResolverOpts,
+};
+use log::error;
+use serde::Deserialize;
+use tokio::net::lookup_host;
+use tokio::sync::Mutex;
+
+use crate::error::GossipError;
+
+/// A source of peer addresses, polled every `discovery_interval` once
+/// handed to `GossipHandle::discover`. Nodes at addresses we don't
+/// know yet are sent our record so they add us and gossip back.
+#[async_trait]
+pub trait Discovery: Send + Sync {
+    /// Addresses of the nodes this source currently knows of.
+    async fn discover(&self) -> Result<Vec<SocketAddr>, GossipError>;
+}
+
+/// A fixed list of addresses.
+pub struct StaticDiscovery {
+    addrs: Vec<SocketAddr>,
+}
+
+impl StaticDiscovery {
+    pub fn new(addrs: Vec<SocketAddr>) -> Self {
+        StaticDiscovery { addrs }
+    }
+}
+
+#[async_trait]
+impl Discovery for StaticDiscovery {
+    async fn discover(&self) -> Result<Vec<SocketAddr>, GossipError> {
+        Ok(self.addrs.clone())
+    }
+}
+
+enum DnsQuery {
+    /// A and AAAA records, each reached on `port`
+    Ip { port: u16 },
+    /// SRV records, whose targets are resolved to A and AAAA records
+    Srv,
+}
+
+/// Addresses from DNS records, looked up with the system resolver
+/// unless a nameserver is set.
+pub struct DnsDiscovery {
+    name: String,
+    query: DnsQuery,
+    nameserver: Option<SocketAddr>,
+}
+
+impl DnsDiscovery {
+    /// Every address `name` resolves to, on `port`.
+    pub fn ip(name: impl Into<String>, port: u16) -> Self {
+        DnsDiscovery {
+            name: name.into(),
+            query: DnsQuery::Ip { port },
+            nameserver: None,
+        }
+    }
+
+    /// The targets and ports of `name`'s SRV records,
+    /// e.g. `_gossip._udp.example.com`.
+    pub fn srv(name: impl Into<String>) -> Self {
+        DnsDiscovery {
+            name: name.into(),
+            query: DnsQuery::Srv,
+            nameserver: None,
+        }
+    }
+
+    /// Query `addr` over UDP instead of the system resolver.
+    pub fn nameserver(mut self, addr: SocketAddr) -> Self {
+        self.nameserver = Some(addr);
+        self
+    }
+
+    fn resolver(&self) -> Result<TokioAsyncResolver, GossipError> {
+        match self.nameserver {
+            Some(addr) => {
+                let mut config = ResolverConfig::new();
+                config.add_name_server(NameServerConfig::new(
+                    addr,
+                    Protocol::Udp,
+                ));
+                Ok(TokioAsyncResolver::tokio(config, ResolverOpts::default()))
+            }
+            None => TokioAsyncResolver::tokio_from_system_conf()
+                .map_err(|e| GossipError::DiscoveryError(e.to_string())),
+        }
+    }
+}
+
+#[async_trait]
+impl Discovery for DnsDiscovery {
+    async fn discover(&self) -> Result<Vec<SocketAddr>, GossipError> {
+        let resolver = self.resolver()?;
+        let lookup_error = |e: hickory_resolver::error::ResolveError| {
+            GossipError::DiscoveryError(e.to_string())
+        };
+
+        match self.query {
+            DnsQuery::Ip { port } => {
+                let ips = resolver
+                    .lookup_ip(self.name.as_str())
+                    .await
+                    .map_err(lookup_error)?;
+                Ok(ips.iter().map(|ip| SocketAddr::new(ip, port)).collect())
+            }
+            DnsQuery::Srv => {
+                let records = resolver
+                    .srv_lookup(self.name.as_str())
+                    .await
+                    .map_err(lookup_error)?;
+
+                let mut addrs = Vec::new();
+                for srv in records.iter() {
+                    let ips = resolver
+                        .lookup_ip(srv.target().clone())
+                        .await
+                        .map_err(lookup_error)?;
+                    addrs.extend(
+                        ips.iter().map(|ip| SocketAddr::new(ip, srv.port())),
+                    );
+                }
+                Ok(addrs)
+            }
+        }
+    }
+}
+
+/// Contents of a peers file, e.g. `{"peers": ["10.0.0.2:7946"]}`
+/// or `peers = ["node-b:7946"]`.
+#[derive(Deserialize)]
+struct PeersFile {
+    peers: Vec<String>,
+}
+
+/// Addresses listed in a JSON or TOML file, chosen by extension.
+/// The file is re-read whenever it is modified; entries may be
+/// hostnames, which are resolved on every poll.
+pub struct FileDiscovery {
+    path: PathBuf,
+    /// Entries as of the file's last modification.
+    cached: Mutex<Option<(SystemTime, Vec<String>)>>,
+}
+
+impl FileDiscovery {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        FileDiscovery {
+            path: path.into(),
+            cached: Mutex::new(None),
+        }
+    }
+
+    async fn entries(&self) -> Result<Vec<String>, GossipError> {
+        let modified = tokio::fs::metadata(&self.path).await?.modified()?;
+
+        let mut cached = self.cached.lock().await;
+        if let Some((at, entries)) = cached.as_ref()
+            && *at == modified
+        {
+            return Ok(entries.clone());
+        }
+
+        let contents = tokio::fs::read_to_string(&self.path).await?;
+        let entries = parse_peers(&self.path, &contents)?;
+        *cached = Some((modified, entries.clone()));
+        Ok(entries)
+    }
+}
+
+fn parse_peers(
+    path: &Path,
+    contents: &str,
+) -> Result<Vec<String>, GossipError> {
+    let file: PeersFile = match path.extension().and_then(|e| e.to_str()) {
+        Some("json") => serde_json::from_str(contents)
+            .map_err(|e| GossipError::DiscoveryError(e.to_string()))?,
+        Some("toml") => toml::from_str(contents)
+            .map_err(|e| GossipError::DiscoveryError(e.to_string()))?,
+        _ => {
+            return Err(GossipError::DiscoveryError(format!(
+                "{} is not a .json or .toml file",
+                path.display()
+            )));
+        }
+    };
+
+    Ok(file.peers)
+}
+
+#[async_trait]
+impl Discovery for FileDiscovery {
+    async fn discover(&self) -> Result<Vec<SocketAddr>, GossipError> {
+        let mut addrs = Vec::new();
+        for entry in self.entries().await? {
+            match lookup_host(entry.as_str()).await {
+                Ok(resolved) => addrs.extend(resolved),
+                Err(e) => error!("Error resolving peer {}: {}", entry, e),
+            }
+        }
+
+        Ok(addrs)
+    }
+}
+
+/// Merges the addresses of several sources. A failing source is
+/// skipped; polling only fails if every source does.
+#[derive(Default)]
+pub struct CompositeDiscovery {
+    sources: Vec<Box<dyn Discovery>>,
+}
+
+impl CompositeDiscovery {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, source: impl Discovery + 'static) {
+        self.sources.push(Box::new(source));
+    }
+}
+
+#[async_trait]
+impl Discovery for CompositeDiscovery {
+    async fn discover(&self) -> Result<Vec<SocketAddr>, GossipError> {
+        let mut seen = HashSet::new();
+        let mut addrs = Vec::new();
+        let mut polled = false;
+        let mut last_error = None;
+
+        for source in &self.sources {
+            match source.discover().await {
+                Ok(found) => {
+                    polled = true;
+                    addrs.extend(found.into_iter().filter(|a| seen.insert(*a)));
+                }
+                Err(e) => {
+                    error!("Error polling discovery source: {}", e);
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        match last_error {
+            Some(e) if !polled => Err(e),
+            _ => Ok(addrs),
+        }
+    }
+}
@@ -16,9 +16,37 @@ pub enum GossipError {
 
     /// Peer errors
     #[error("Peer error: {0}")]
-    PeerError(#[from] tailscale_api::APIError),
+    PeerError(String),
 
     /// IP address errors
     #[error("IP address error: {0}")]
     IpAddressError(String),
+
+    /// A discovery source couldn't be polled
+    #[error("Discovery error: {0}")]
+    DiscoveryError(String),
+
+    /// Configuration the node cannot be started with
+    #[error("Invalid config: {0}")]
+    InvalidConfig(String),
+
+    /// Keyring errors
+    #[error("Keyring error: {0}")]
+    KeyringError(String),
+
+    /// Message from a peer speaking another wire format
+    #[error("Unsupported protocol version: {0}")]
+    UnsupportedVersion(u8),
+
+    /// Operation the transport doesn't provide
+    #[error("Unsupported: {0}")]
+    Unsupported(String),
+
+    /// No reply arrived in time
+    #[error("Timed out: {0}")]
+    Timeout(String),
+
+    /// Payload does not fit in a single message
+    #[error("Payload too large: {0} bytes")]
+    PayloadTooLarge(usize),
 }
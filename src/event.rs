@@ -0,0 +1,47 @@
+use crate::node::{Node, NodeStatus};
+
+/// Change in cluster membership observed by the local node.
+#[derive(Debug, Clone)]
+pub enum MembershipEvent {
+    /// A node we didn't know about appeared.
+    Joined(Node),
+    /// A node failed a probe and is suspected of having failed.
+    Suspected(Node),
+    /// A node was declared offline.
+    Offline(Node),
+    /// A suspect, offline or departed node is back online.
+    Recovered(Node),
+    /// A node announced it is leaving the cluster.
+    Left(Node),
+    /// A node's record changed without changing its liveness.
+    Updated(Node),
+}
+
+impl MembershipEvent {
+    /// Event for `node` having moved from the `previous` status
+    /// to its current one.
+    pub(crate) fn transition(previous: &NodeStatus, node: &Node) -> Self {
+        let node = node.clone();
+
+        match node.status {
+            NodeStatus::Suspect => MembershipEvent::Suspected(node),
+            NodeStatus::Offline => MembershipEvent::Offline(node),
+            NodeStatus::Left => MembershipEvent::Left(node),
+            NodeStatus::Online if *previous != NodeStatus::Online => {
+                MembershipEvent::Recovered(node)
+            }
+            NodeStatus::Online => MembershipEvent::Updated(node),
+        }
+    }
+
+    pub fn node(&self) -> &Node {
+        match self {
+            MembershipEvent::Joined(node)
+            | MembershipEvent::Suspected(node)
+            | MembershipEvent::Offline(node)
+            | MembershipEvent::Recovered(node)
+            | MembershipEvent::Left(node)
+            | MembershipEvent::Updated(node) => node,
+        }
+    }
+}
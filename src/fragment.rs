@@ -0,0 +1,127 @@
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+use crate::message::{Fragment, GossipMessage, MessageId};
+
+/// A message whose fragments are still arriving. Each chunk is kept
+/// with the signed message that carried it, for relaying.
+struct Partial {
+    msg_type: u16,
+    count: u16,
+    chunks: BTreeMap<u16, (Vec<u8>, GossipMessage)>,
+    size: usize,
+    started: Instant,
+}
+
+/// A message whose last fragment has arrived.
+pub struct Reassembled {
+    pub id: MessageId,
+    pub msg_type: u16,
+    pub payload: Vec<u8>,
+    /// The signed fragments it arrived in, in order.
+    pub fragments: Vec<GossipMessage>,
+}
+
+/// Buffers fragments until every chunk of a message has arrived.
+/// Messages are dropped if they aren't complete within `timeout`,
+/// if they exceed `max_size`, or to make room once `capacity`
+/// messages are in flight.
+pub struct Reassembler {
+    capacity: usize,
+    timeout: Duration,
+    max_size: usize,
+    partials: HashMap<MessageId, Partial>,
+}
+
+impl Reassembler {
+    pub fn new(capacity: usize, timeout: Duration, max_size: usize) -> Self {
+        Reassembler {
+            capacity,
+            timeout,
+            max_size,
+            partials: HashMap::new(),
+        }
+    }
+
+    /// Add a fragment carried by `msg`. Returns the whole message
+    /// once the last missing fragment arrives.
+    pub fn insert(
+        &mut self,
+        msg: GossipMessage,
+        fragment: Fragment,
+    ) -> Option<Reassembled> {
+        self.evict();
+
+        let id = MessageId {
+            origin: msg.from_id,
+            seq: fragment.seq,
+        };
+        // fragments are never empty, so `size` bounds the chunk count
+        if fragment.index >= fragment.count || fragment.data.is_empty() {
+            return None;
+        }
+
+        if !self.partials.contains_key(&id) {
+            if self.partials.len() >= self.capacity {
+                self.evict_oldest();
+            }
+
+            self.partials.insert(
+                id,
+                Partial {
+                    msg_type: fragment.msg_type,
+                    count: fragment.count,
+                    chunks: BTreeMap::new(),
+                    size: 0,
+                    started: Instant::now(),
+                },
+            );
+        }
+
+        let partial = self.partials.get_mut(&id)?;
+        if partial.msg_type != fragment.msg_type
+            || partial.count != fragment.count
+            || partial.chunks.contains_key(&fragment.index)
+        {
+            return None;
+        }
+
+        partial.size += fragment.data.len();
+        if partial.size > self.max_size {
+            self.partials.remove(&id);
+            return None;
+        }
+
+        partial.chunks.insert(fragment.index, (fragment.data, msg));
+        if partial.chunks.len() < partial.count as usize {
+            return None;
+        }
+
+        let partial = self.partials.remove(&id)?;
+        let (chunks, fragments): (Vec<_>, Vec<_>) =
+            partial.chunks.into_values().unzip();
+        Some(Reassembled {
+            id,
+            msg_type: partial.msg_type,
+            payload: chunks.concat(),
+            fragments,
+        })
+    }
+
+    fn evict(&mut self) {
+        let timeout = self.timeout;
+        self.partials.retain(|_, p| p.started.elapsed() < timeout);
+    }
+
+    fn evict_oldest(&mut self) {
+        let oldest = self
+            .partials
+            .iter()
+            .min_by_key(|(_, p)| p.started)
+            .map(|(id, _)| *id);
+
+        if let Some(id) = oldest {
+            self.partials.remove(&id);
+        }
+    }
+}
@@ -0,0 +1,271 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::error;
+use serde::{Serialize, de::DeserializeOwned};
+use tokio::{sync::broadcast, task::JoinHandle};
+
+use crate::discovery::Discovery;
+use crate::error::GossipError;
+use crate::event::MembershipEvent;
+use crate::keyring::KEY_SIZE;
+use crate::kv::KvEvent;
+use crate::message::GossipMessage;
+use crate::node::{Node, NodeId};
+use crate::protocol::GossipProtocol;
+use crate::topic::TopicMessage;
+
+/// Handle to a running gossip node.
+/// Returned by `start`; the protocol loops run as tasks on the
+/// caller's runtime until `shutdown` is called.
+pub struct GossipHandle {
+    protocol: Arc<GossipProtocol>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl GossipHandle {
+    pub(crate) fn new(
+        protocol: Arc<GossipProtocol>,
+        tasks: Vec<JoinHandle<()>>,
+    ) -> Self {
+        GossipHandle { protocol, tasks }
+    }
+
+    /// Poll a discovery source for peers every `discovery_interval`,
+    /// so nodes that join later are found without having to contact
+    /// us first. Sources can be merged with `CompositeDiscovery`.
+    /// The loop stops with the node.
+    pub fn discover(&mut self, discovery: impl Discovery + 'static) {
+        let protocol = Arc::clone(&self.protocol);
+        self.tasks.push(tokio::spawn(async move {
+            protocol.start_discovery(Box::new(discovery)).await
+        }));
+    }
+
+    /// Gossip an application message of type `msg_type` to the cluster.
+    /// The payload is encoded with postcard; receivers read it back
+    /// with `GossipMessage::decode`. Payloads too large for a datagram
+    /// are fragmented, up to `GossipConfig::max_message_size`.
+    pub async fn broadcast<T: Serialize + ?Sized>(
+        &self,
+        msg_type: u16,
+        payload: &T,
+    ) -> Result<(), GossipError> {
+        self.protocol.broadcast(msg_type, payload).await
+    }
+
+    /// Publish a message to the subscribers of `topic`, encoded with
+    /// postcard. It is gossiped to subscribed peers first; peers that
+    /// don't subscribe still relay it within `message_ttl` hops.
+    /// Without transport streams it must fit a single datagram.
+    pub async fn publish<T: Serialize + ?Sized>(
+        &self,
+        topic: &str,
+        payload: &T,
+    ) -> Result<(), GossipError> {
+        self.protocol.publish(topic, payload).await
+    }
+
+    /// Receive messages published to `topic`. The subscription is
+    /// advertised in our record so publishers send to us first, and
+    /// lasts until `unsubscribe_topic` even if every receiver is
+    /// dropped. Fails with `PayloadTooLarge` once the record's topics
+    /// no longer fit a datagram of `max_datagram_size`.
+    pub async fn subscribe_topic(
+        &self,
+        topic: impl Into<String>,
+    ) -> Result<broadcast::Receiver<TopicMessage>, GossipError> {
+        self.protocol.subscribe_topic(topic.into()).await
+    }
+
+    /// Stop receiving messages published to `topic`.
+    pub async fn unsubscribe_topic(
+        &self,
+        topic: &str,
+    ) -> Result<(), GossipError> {
+        self.protocol.unsubscribe_topic(topic).await
+    }
+
+    /// Peers subscribed to `topic`, excluding the local node.
+    pub async fn subscribers(&self, topic: &str) -> Vec<Node> {
+        self.protocol.subscribers(topic).await
+    }
+
+    /// Send an application message of type `msg_type` to one node.
+    /// It reaches the node's subscribers and handler like a broadcast,
+    /// but isn't forwarded to anyone else.
+    pub async fn send_to<T: Serialize + ?Sized>(
+        &self,
+        node_id: NodeId,
+        msg_type: u16,
+        payload: &T,
+    ) -> Result<(), GossipError> {
+        self.protocol.send_to(node_id, msg_type, payload).await
+    }
+
+    /// Ask one node a question: send it a request of type `msg_type`
+    /// and decode the reply of the responder it registered with
+    /// `Handlers::respond`. Fails with `GossipError::Timeout` if no
+    /// reply arrives within `wait`. Without transport streams, the
+    /// request and its reply must each fit a single datagram.
+    pub async fn request<T, R>(
+        &self,
+        node_id: NodeId,
+        msg_type: u16,
+        payload: &T,
+        wait: Duration,
+    ) -> Result<R, GossipError>
+    where
+        T: Serialize + ?Sized,
+        R: DeserializeOwned,
+    {
+        self.protocol
+            .request(node_id, msg_type, payload, wait)
+            .await?
+            .decode()
+    }
+
+    /// Add a cluster key that incoming packets may be sealed with.
+    /// Keys are rotated by installing the new key on every node,
+    /// switching each to it with `use_key`, then removing the old one.
+    pub fn install_key(&self, key: [u8; KEY_SIZE]) -> Result<(), GossipError> {
+        self.protocol.install_key(key)
+    }
+
+    /// Start sealing outgoing packets with an installed key.
+    pub fn use_key(&self, key: [u8; KEY_SIZE]) -> Result<(), GossipError> {
+        self.protocol.use_key(key)
+    }
+
+    /// Stop accepting packets sealed with a retired key.
+    pub fn remove_key(&self, key: [u8; KEY_SIZE]) -> Result<(), GossipError> {
+        self.protocol.remove_key(key)
+    }
+
+    /// Number of packets dropped because they failed authentication,
+    /// e.g. sent without or with an unknown cluster key.
+    pub fn unauthenticated_packets(&self) -> u64 {
+        self.protocol.unauthenticated_packets()
+    }
+
+    /// Every known peer, excluding the local node.
+    pub async fn peers(&self) -> Vec<Node> {
+        self.protocol.peers().await
+    }
+
+    /// Peers carrying `tag`, excluding the local node.
+    pub async fn peers_with_tag(&self, tag: &str) -> Vec<Node> {
+        self.protocol.peers_with_tag(tag).await
+    }
+
+    pub fn local_node(&self) -> Node {
+        self.protocol.local_node()
+    }
+
+    /// Set a metadata entry and gossip the change.
+    pub async fn set_metadata(
+        &self,
+        key: impl Into<String>,
+        value: impl Into<String>,
+    ) -> Result<(), GossipError> {
+        let (key, value) = (key.into(), value.into());
+        self.protocol
+            .update_local(|node| {
+                node.metadata.insert(key, value);
+            })
+            .await
+    }
+
+    pub async fn remove_metadata(&self, key: &str) -> Result<(), GossipError> {
+        self.protocol
+            .update_local(|node| {
+                node.metadata.remove(key);
+            })
+            .await
+    }
+
+    /// Add a tag and gossip the change.
+    pub async fn add_tag(
+        &self,
+        tag: impl Into<String>,
+    ) -> Result<(), GossipError> {
+        let tag = tag.into();
+        self.protocol
+            .update_local(|node| {
+                node.tags.insert(tag);
+            })
+            .await
+    }
+
+    pub async fn remove_tag(&self, tag: &str) -> Result<(), GossipError> {
+        self.protocol
+            .update_local(|node| {
+                node.tags.remove(tag);
+            })
+            .await
+    }
+
+    /// Replicate `value` under `key`, encoded with postcard.
+    /// Concurrent writes to a key are resolved in favour of the
+    /// latest, by hybrid logical clock. The entry must fit a single
+    /// datagram.
+    pub async fn set<T: Serialize + ?Sized>(
+        &self,
+        key: impl Into<String>,
+        value: &T,
+    ) -> Result<(), GossipError> {
+        let value = postcard::to_allocvec(value)?;
+        self.protocol.set(key.into(), Some(value)).await
+    }
+
+    /// The value replicated under `key`, if it is set.
+    pub async fn get<T: DeserializeOwned>(
+        &self,
+        key: &str,
+    ) -> Result<Option<T>, GossipError> {
+        match self.protocol.get(key).await {
+            Some(value) => Ok(Some(postcard::from_bytes(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Delete a replicated key.
+    pub async fn delete(&self, key: &str) -> Result<(), GossipError> {
+        self.protocol.set(key.to_string(), None).await
+    }
+
+    /// Receive changes to replicated keys, made locally or by peers.
+    pub fn watch(&self) -> broadcast::Receiver<KvEvent> {
+        self.protocol.watch()
+    }
+
+    /// Receive application messages delivered to this node.
+    pub fn subscribe(&self) -> broadcast::Receiver<GossipMessage> {
+        self.protocol.subscribe()
+    }
+
+    /// Receive membership changes: joins, suspicions, failures,
+    /// recoveries, departures and record updates.
+    pub fn events(&self) -> broadcast::Receiver<MembershipEvent> {
+        self.protocol.events()
+    }
+
+    /// Leave the cluster and stop the node.
+    /// Peers are sent a `leave` message, the protocol loops finish any
+    /// in-flight sends and return, then the transport is closed.
+    pub async fn shutdown(self) -> Result<(), GossipError> {
+        if let Err(e) = self.protocol.leave().await {
+            error!("Error sending leave: {}", e);
+        }
+
+        self.protocol.stop();
+
+        for task in self.tasks {
+            if let Err(e) = task.await {
+                error!("Protocol task failed: {}", e);
+            }
+        }
+
+        self.protocol.close().await
+    }
+}
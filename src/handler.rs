@@ -0,0 +1,119 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use serde::Serialize;
+
+use crate::error::GossipError;
+use crate::message::GossipMessage;
+use crate::node::Node;
+
+/// Handler for application messages of a single `msg_type`.
+/// The payload is read with `GossipMessage::decode`.
+#[async_trait]
+pub trait MessageHandler: Send + Sync {
+    async fn handle(&self, from: Node, msg: GossipMessage);
+}
+
+#[async_trait]
+impl<F, Fut> MessageHandler for F
+where
+    F: Fn(Node, GossipMessage) -> Fut + Send + Sync,
+    Fut: Future<Output = ()> + Send,
+{
+    async fn handle(&self, from: Node, msg: GossipMessage) {
+        self(from, msg).await
+    }
+}
+
+/// Responder to requests of a single `msg_type`, sent with
+/// `GossipHandle::request`. Its reply is encoded with postcard and
+/// read by the requester with `GossipMessage::decode`.
+#[async_trait]
+pub trait Responder: Send + Sync {
+    type Reply: Serialize + Send;
+
+    async fn respond(&self, from: Node, msg: GossipMessage) -> Self::Reply;
+}
+
+#[async_trait]
+impl<F, Fut, R> Responder for F
+where
+    F: Fn(Node, GossipMessage) -> Fut + Send + Sync,
+    Fut: Future<Output = R> + Send,
+    R: Serialize + Send,
+{
+    type Reply = R;
+
+    async fn respond(&self, from: Node, msg: GossipMessage) -> R {
+        self(from, msg).await
+    }
+}
+
+/// A `Responder` with its reply type erased, so responders with
+/// different replies share a registry.
+#[async_trait]
+pub(crate) trait EncodingResponder: Send + Sync {
+    async fn respond(
+        &self,
+        from: Node,
+        msg: GossipMessage,
+    ) -> Result<Vec<u8>, GossipError>;
+}
+
+#[async_trait]
+impl<R: Responder> EncodingResponder for R {
+    async fn respond(
+        &self,
+        from: Node,
+        msg: GossipMessage,
+    ) -> Result<Vec<u8>, GossipError> {
+        let reply = Responder::respond(self, from, msg).await;
+        Ok(postcard::to_allocvec(&reply)?)
+    }
+}
+
+/// Registry of application message handlers and request responders
+/// keyed by `msg_type`.
+#[derive(Clone, Default)]
+pub struct Handlers {
+    handlers: HashMap<u16, Arc<dyn MessageHandler>>,
+    responders: HashMap<u16, Arc<dyn EncodingResponder>>,
+}
+
+impl Handlers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a handler for `msg_type`, replacing any existing one.
+    pub fn register<H>(&mut self, msg_type: u16, handler: H) -> &mut Self
+    where
+        H: MessageHandler + 'static,
+    {
+        self.handlers.insert(msg_type, Arc::new(handler));
+        self
+    }
+
+    /// Register a responder for requests of `msg_type`, replacing any
+    /// existing one.
+    pub fn respond<R>(&mut self, msg_type: u16, responder: R) -> &mut Self
+    where
+        R: Responder + 'static,
+    {
+        self.responders.insert(msg_type, Arc::new(responder));
+        self
+    }
+
+    pub(crate) fn get(&self, msg_type: u16) -> Option<Arc<dyn MessageHandler>> {
+        self.handlers.get(&msg_type).cloned()
+    }
+
+    pub(crate) fn responder(
+        &self,
+        msg_type: u16,
+    ) -> Option<Arc<dyn EncodingResponder>> {
+        self.responders.get(&msg_type).cloned()
+    }
+}
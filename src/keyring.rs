@@ -0,0 +1,107 @@
+use std::fmt;
+
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use rand::Rng;
+
+use crate::error::GossipError;
+
+/// Size of a cluster key in bytes.
+pub const KEY_SIZE: usize = 32;
+
+const NONCE_SIZE: usize = 24;
+const TAG_SIZE: usize = 16;
+
+/// Bytes added to every datagram by encryption.
+pub const ENCRYPTION_OVERHEAD: usize = NONCE_SIZE + TAG_SIZE;
+
+/// Shared cluster keys used to encrypt and authenticate gossip with
+/// XChaCha20-Poly1305. Packets are sealed with the primary key and
+/// opened with any installed key, so keys can be rotated without a
+/// partition: install the new key everywhere, switch every node to
+/// it with `use_key`, then remove the old one.
+#[derive(Clone)]
+pub struct Keyring {
+    /// Installed keys; the first is the primary.
+    keys: Vec<[u8; KEY_SIZE]>,
+}
+
+impl fmt::Debug for Keyring {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Keyring")
+            .field("keys", &self.keys.len())
+            .finish()
+    }
+}
+
+impl Keyring {
+    pub fn new(primary: [u8; KEY_SIZE]) -> Self {
+        Keyring {
+            keys: vec![primary],
+        }
+    }
+
+    /// Add a key that packets may be opened with.
+    pub fn install(&mut self, key: [u8; KEY_SIZE]) {
+        if !self.keys.contains(&key) {
+            self.keys.push(key);
+        }
+    }
+
+    /// Make an installed key the primary key.
+    pub fn use_key(&mut self, key: [u8; KEY_SIZE]) -> Result<(), GossipError> {
+        let Some(i) = self.keys.iter().position(|k| *k == key) else {
+            return Err(GossipError::KeyringError(
+                "key is not installed".to_string(),
+            ));
+        };
+
+        self.keys[..=i].rotate_right(1);
+        Ok(())
+    }
+
+    /// Remove a secondary key. The primary key can't be removed.
+    pub fn remove(&mut self, key: [u8; KEY_SIZE]) -> Result<(), GossipError> {
+        if self.keys[0] == key {
+            return Err(GossipError::KeyringError(
+                "can't remove the primary key".to_string(),
+            ));
+        }
+
+        self.keys.retain(|k| *k != key);
+        Ok(())
+    }
+
+    /// Encrypt a datagram with the primary key as `nonce || ciphertext`.
+    pub fn seal(&self, plaintext: &[u8]) -> Result<Vec<u8>, GossipError> {
+        let mut nonce = [0u8; NONCE_SIZE];
+        rand::rng().fill(&mut nonce[..]);
+
+        let cipher = XChaCha20Poly1305::new(Key::from_slice(&self.keys[0]));
+        let ciphertext = cipher
+            .encrypt(XNonce::from_slice(&nonce), plaintext)
+            .map_err(|_| {
+                GossipError::KeyringError("encryption failed".to_string())
+            })?;
+
+        let mut packet = Vec::with_capacity(NONCE_SIZE + ciphertext.len());
+        packet.extend_from_slice(&nonce);
+        packet.extend_from_slice(&ciphertext);
+        Ok(packet)
+    }
+
+    /// Decrypt a datagram with whichever installed key sealed it.
+    /// Returns `None` if no key authenticates the packet.
+    pub fn open(&self, packet: &[u8]) -> Option<Vec<u8>> {
+        if packet.len() < ENCRYPTION_OVERHEAD {
+            return None;
+        }
+
+        let (nonce, ciphertext) = packet.split_at(NONCE_SIZE);
+        self.keys.iter().find_map(|key| {
+            XChaCha20Poly1305::new(Key::from_slice(key))
+                .decrypt(XNonce::from_slice(nonce), ciphertext)
+                .ok()
+        })
+    }
+}
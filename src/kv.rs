@@ -0,0 +1,154 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+
+use crate::error::GossipError;
+use crate::node::NodeId;
+
+/// Hybrid logical clock reading: wall-clock milliseconds, a counter
+/// ordering events within the same millisecond, and the node that
+/// took it so concurrent writes are ordered the same everywhere.
+#[derive(
+    Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord,
+)]
+pub struct Timestamp {
+    pub wall: u64,
+    pub logical: u32,
+    pub node: NodeId,
+}
+
+/// Hybrid logical clock. Readings never go backwards, and move past
+/// every timestamp observed from peers, so a write always supersedes
+/// the writes its node has seen even if wall clocks disagree.
+struct Hlc {
+    wall: u64,
+    logical: u32,
+}
+
+impl Hlc {
+    fn new() -> Self {
+        Hlc {
+            wall: 0,
+            logical: 0,
+        }
+    }
+
+    fn now(&mut self, node: NodeId) -> Timestamp {
+        let physical = physical_now();
+        if physical > self.wall {
+            self.wall = physical;
+            self.logical = 0;
+        } else {
+            self.logical += 1;
+        }
+
+        Timestamp {
+            wall: self.wall,
+            logical: self.logical,
+            node,
+        }
+    }
+
+    fn observe(&mut self, ts: &Timestamp) {
+        if ts.wall > self.wall {
+            self.wall = ts.wall;
+            self.logical = ts.logical;
+        } else if ts.wall == self.wall {
+            self.logical = self.logical.max(ts.logical);
+        }
+    }
+}
+
+fn physical_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// A key's replicated value, or a tombstone if it was deleted.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub value: Option<Vec<u8>>,
+    pub ts: Timestamp,
+}
+
+/// Change to a key, local or merged from a peer.
+#[derive(Debug, Clone)]
+pub struct KvEvent {
+    pub key: String,
+    /// The new value; `None` if the key was deleted
+    pub value: Option<Vec<u8>>,
+}
+
+impl KvEvent {
+    /// Decode the new value with postcard.
+    pub fn decode<T: DeserializeOwned>(
+        &self,
+    ) -> Result<Option<T>, GossipError> {
+        self.value
+            .as_deref()
+            .map(postcard::from_bytes)
+            .transpose()
+            .map_err(GossipError::from)
+    }
+}
+
+/// Last-writer-wins map. Each key keeps the entry with the highest
+/// timestamp; deletes are kept as tombstones until `expire` so older
+/// writes still in flight can't bring the key back.
+pub struct KvStore {
+    node: NodeId,
+    clock: Hlc,
+    entries: BTreeMap<String, Entry>,
+}
+
+impl KvStore {
+    pub fn new(node: NodeId) -> Self {
+        KvStore {
+            node,
+            clock: Hlc::new(),
+            entries: BTreeMap::new(),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&[u8]> {
+        self.entries.get(key)?.value.as_deref()
+    }
+
+    /// Entry writing `value` to a key, newer than any seen so far.
+    /// It takes effect once merged.
+    pub fn write(&mut self, value: Option<Vec<u8>>) -> Entry {
+        Entry {
+            value,
+            ts: self.clock.now(self.node),
+        }
+    }
+
+    /// Keep `entry` if it is newer than the key's current one.
+    /// Returns whether it was kept.
+    pub fn merge(&mut self, key: &str, entry: Entry) -> bool {
+        self.clock.observe(&entry.ts);
+
+        match self.entries.get(key) {
+            Some(current) if current.ts >= entry.ts => false,
+            _ => {
+                self.entries.insert(key.to_string(), entry);
+                true
+            }
+        }
+    }
+
+    /// Every entry, tombstones included.
+    pub fn entries(&self) -> impl Iterator<Item = (&String, &Entry)> {
+        self.entries.iter()
+    }
+
+    /// Drop tombstones older than `ttl`.
+    pub fn expire(&mut self, ttl: Duration) {
+        let cutoff = physical_now().saturating_sub(ttl.as_millis() as u64);
+        self.entries
+            .retain(|_, e| e.value.is_some() || e.ts.wall >= cutoff);
+    }
+}
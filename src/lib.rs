@@ -1,40 +1,80 @@
 mod config;
 pub mod constants;
+pub mod discovery;
 mod error;
+mod event;
+mod fragment;
+mod handle;
+mod handler;
+pub mod keyring;
+pub mod kv;
+pub mod memory;
 pub mod message;
 mod node;
 mod protocol;
 mod retry;
+mod seen;
+pub mod stream;
 pub mod tailscale;
+pub mod topic;
+pub mod udp;
 pub mod util;
 
-use error::GossipError;
-use node::Node;
-use protocol::GossipTransport;
 use std::{
     collections::HashMap,
-    net::{Ipv4Addr, SocketAddr},
+    net::{IpAddr, SocketAddr},
     sync::Arc,
-    thread,
 };
-use tokio::runtime::Builder;
 
 pub use config::GossipConfig;
+pub use discovery::Discovery;
+pub use error::GossipError;
+pub use event::MembershipEvent;
+pub use handle::GossipHandle;
+pub use handler::{Handlers, MessageHandler, Responder};
+pub use keyring::Keyring;
+pub use node::{Node, NodeId, NodeStatus};
+pub use protocol::GossipTransport;
+pub use stream::GossipStream;
+pub use topic::TopicMessage;
 
+use crate::constants::{MAX_METADATA_SIZE, MIN_DATAGRAM_SIZE};
 use crate::util::hash_node_name;
 
+/// Start a gossip node on the current tokio runtime.
+/// The heartbeat, gossip, probe and receive loops are spawned as tasks and
+/// the returned handle is used to interact with and stop them.
 pub async fn start(
     gossip_config: GossipConfig,
     transport: Box<dyn GossipTransport>,
-    seed_peers: HashMap<u32, Node>,
-) -> Result<(), GossipError> {
+    seed_peers: HashMap<NodeId, Node>,
+    handlers: Handlers,
+) -> Result<GossipHandle, GossipError> {
     // get the ip from the transport
     // and define the local node
-    let ip = gossip_config.ip_address.parse::<Ipv4Addr>().unwrap();
-    let local = Node::new(
+    if gossip_config.max_datagram_size < MIN_DATAGRAM_SIZE {
+        return Err(GossipError::InvalidConfig(format!(
+            "max_datagram_size must be at least {} bytes",
+            MIN_DATAGRAM_SIZE
+        )));
+    }
+
+    let ip = gossip_config.ip_address.parse::<IpAddr>().map_err(|_| {
+        GossipError::IpAddressError(gossip_config.ip_address.clone())
+    })?;
+    let mut local = Node::new(
         hash_node_name(&gossip_config.node_name),
         SocketAddr::from((ip, gossip_config.gossip_port)),
     );
+    local.metadata = gossip_config.metadata.clone();
+    local.tags = gossip_config.tags.clone();
+
+    if local.metadata_size() > MAX_METADATA_SIZE {
+        return Err(GossipError::InvalidConfig(format!(
+            "metadata and tags must encode to at most {} bytes",
+            MAX_METADATA_SIZE
+        )));
+    }
 
     // initialize the gossip protocol
     let p = Arc::new(protocol::GossipProtocol::new(
@@ -42,37 +82,35 @@ pub async fn start(
         local,
         seed_peers,
         transport,
+        handlers,
     ));
 
-    let p1 = Arc::clone(&p);
-
-    let handles = [
-        thread::spawn(move || {
-            let runtime = Builder::new_multi_thread()
-                .worker_threads(1)
-                .enable_time()
-                .build()
-                .unwrap();
-            // let runtime = tokio::runtime::Runtime::new().unwrap();
-            runtime.block_on(p1.start_heartbeat());
+    let tasks = vec![
+        tokio::spawn({
+            let p = Arc::clone(&p);
+            async move { p.start_heartbeat().await }
+        }),
+        tokio::spawn({
+            let p = Arc::clone(&p);
+            async move { p.start_gossip().await }
         }),
-        thread::spawn(move || {
-            // let runtime = tokio::runtime::Runtime::new().unwrap();
-            let runtime = Builder::new_multi_thread()
-                .worker_threads(1)
-                .enable_time()
-                .build()
-                .unwrap();
-            runtime.block_on(p.start_receive());
+        tokio::spawn({
+            let p = Arc::clone(&p);
+            async move { p.start_probe().await }
+        }),
+        tokio::spawn({
+            let p = Arc::clone(&p);
+            async move { p.start_receive().await }
+        }),
+        tokio::spawn({
+            let p = Arc::clone(&p);
+            async move { p.start_sync().await }
+        }),
+        tokio::spawn({
+            let p = Arc::clone(&p);
+            async move { p.start_streams().await }
         }),
     ];
 
-    for handle in handles {
-        match handle.join() {
-            Ok(_) => println!("thread done"),
-            Err(e) => println!("Thread panicked: {:?}", e),
-        }
-    }
-
-    Ok(())
+    Ok(GossipHandle::new(p, tasks))
 }
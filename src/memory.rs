@@ -0,0 +1,279 @@
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use tokio::io::{DuplexStream, duplex};
+use tokio::sync::{Mutex, mpsc};
+use tokio::time::sleep;
+
+use crate::error::GossipError;
+use crate::protocol::GossipTransport;
+use crate::stream::GossipStream;
+
+type Packet = (Vec<u8>, SocketAddr);
+type Incoming = (DuplexStream, SocketAddr);
+
+/// Bytes buffered in each direction of a stream.
+const STREAM_BUFFER: usize = 64 * 1024;
+
+/// Conditions applied to every packet sent across a `MemoryNetwork`.
+#[derive(Debug, Clone, Default)]
+pub struct LinkConditions {
+    /// Fixed delay added to every packet
+    pub latency: Duration,
+    /// Extra random delay in `0..jitter`; packets overtake each other
+    /// when it exceeds their spacing, which reorders delivery
+    pub jitter: Duration,
+    /// Probability in `0.0..=1.0` that a packet is dropped
+    pub drop_rate: f64,
+    /// Probability in `0.0..=1.0` that a packet is delivered twice
+    pub duplicate_rate: f64,
+}
+
+struct NetworkState {
+    nodes: HashMap<SocketAddr, mpsc::UnboundedSender<Packet>>,
+    /// Where streams opened to each transport are handed over.
+    listeners: HashMap<SocketAddr, mpsc::UnboundedSender<Incoming>>,
+    /// Whether transports offer streams, or only datagrams.
+    streams: bool,
+    conditions: LinkConditions,
+    /// Directed links that currently drop everything.
+    blocked: HashSet<(SocketAddr, SocketAddr)>,
+    rng: StdRng,
+}
+
+/// In-process network connecting `MemoryTransport`s through channels.
+/// Lets the protocol run without sockets, with knobs for latency,
+/// loss, duplication, reordering and partitions.
+#[derive(Clone)]
+pub struct MemoryNetwork {
+    state: Arc<StdMutex<NetworkState>>,
+}
+
+impl Default for MemoryNetwork {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoryNetwork {
+    pub fn new() -> Self {
+        Self::with_rng(StdRng::from_os_rng())
+    }
+
+    /// Network whose drop and duplication decisions are reproducible.
+    pub fn with_seed(seed: u64) -> Self {
+        Self::with_rng(StdRng::seed_from_u64(seed))
+    }
+
+    fn with_rng(rng: StdRng) -> Self {
+        MemoryNetwork {
+            state: Arc::new(StdMutex::new(NetworkState {
+                nodes: HashMap::new(),
+                listeners: HashMap::new(),
+                streams: true,
+                conditions: LinkConditions::default(),
+                blocked: HashSet::new(),
+                rng,
+            })),
+        }
+    }
+
+    /// Attach a transport at `addr`, replacing any existing one.
+    pub fn transport(&self, addr: SocketAddr) -> MemoryTransport {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let (streams_tx, streams_rx) = mpsc::unbounded_channel();
+
+        let mut state = self.state.lock().unwrap();
+        state.nodes.insert(addr, tx);
+        state.listeners.insert(addr, streams_tx);
+
+        MemoryTransport {
+            addr,
+            network: self.clone(),
+            rx: Mutex::new(rx),
+            streams: Mutex::new(streams_rx),
+        }
+    }
+
+    pub fn set_conditions(&self, conditions: LinkConditions) {
+        self.state.lock().unwrap().conditions = conditions;
+    }
+
+    /// Offer streams on every transport, or only datagrams. Takes
+    /// effect for nodes started afterwards.
+    pub fn set_streams(&self, enabled: bool) {
+        self.state.lock().unwrap().streams = enabled;
+    }
+
+    /// Cut every link between the two groups, in both directions.
+    pub fn partition(&self, left: &[SocketAddr], right: &[SocketAddr]) {
+        let mut state = self.state.lock().unwrap();
+
+        for a in left {
+            for b in right {
+                state.blocked.insert((*a, *b));
+                state.blocked.insert((*b, *a));
+            }
+        }
+    }
+
+    /// Restore every link cut by `partition`.
+    pub fn heal(&self) {
+        self.state.lock().unwrap().blocked.clear();
+    }
+
+    fn detach(&self, addr: SocketAddr) {
+        let mut state = self.state.lock().unwrap();
+        state.nodes.remove(&addr);
+        state.listeners.remove(&addr);
+    }
+
+    /// Open a stream from `from` to `to`. Streams are reliable, so
+    /// link conditions don't apply; only a partition or a missing
+    /// transport stops the connection.
+    fn connect(
+        &self,
+        from: SocketAddr,
+        to: SocketAddr,
+    ) -> Result<DuplexStream, GossipError> {
+        let state = self.state.lock().unwrap();
+
+        let listener = state
+            .listeners
+            .get(&to)
+            .filter(|_| !state.blocked.contains(&(from, to)))
+            .ok_or_else(|| {
+                GossipError::NetworkError(format!("{} is unreachable", to))
+            })?;
+
+        let (local, remote) = duplex(STREAM_BUFFER);
+        listener.send((remote, from)).map_err(|_| {
+            GossipError::NetworkError(format!("{} is closed", to))
+        })?;
+
+        Ok(local)
+    }
+
+    /// Route a packet, applying the current link conditions.
+    /// Like UDP, undeliverable packets are silently discarded.
+    fn deliver(&self, from: SocketAddr, to: SocketAddr, data: &[u8]) {
+        let mut state = self.state.lock().unwrap();
+
+        if state.blocked.contains(&(from, to)) {
+            return;
+        }
+
+        let Some(tx) = state.nodes.get(&to).cloned() else {
+            return;
+        };
+
+        let conditions = state.conditions.clone();
+        if state.rng.random_bool(conditions.drop_rate) {
+            return;
+        }
+
+        let copies = if state.rng.random_bool(conditions.duplicate_rate) {
+            2
+        } else {
+            1
+        };
+
+        for _ in 0..copies {
+            let mut delay = conditions.latency;
+            if !conditions.jitter.is_zero() {
+                delay += conditions.jitter.mul_f64(state.rng.random::<f64>());
+            }
+
+            let packet = (data.to_vec(), from);
+            if delay.is_zero() {
+                let _ = tx.send(packet);
+            } else {
+                let tx = tx.clone();
+                tokio::spawn(async move {
+                    sleep(delay).await;
+                    let _ = tx.send(packet);
+                });
+            }
+        }
+    }
+}
+
+/// `GossipTransport` endpoint on a `MemoryNetwork`.
+pub struct MemoryTransport {
+    addr: SocketAddr,
+    network: MemoryNetwork,
+    rx: Mutex<mpsc::UnboundedReceiver<Packet>>,
+    streams: Mutex<mpsc::UnboundedReceiver<Incoming>>,
+}
+
+impl MemoryTransport {
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+#[async_trait]
+impl GossipTransport for MemoryTransport {
+    async fn write(
+        &self,
+        buf: &[u8],
+        addr: String,
+    ) -> Result<usize, GossipError> {
+        let to = addr
+            .parse::<SocketAddr>()
+            .map_err(|_| GossipError::IpAddressError(addr))?;
+
+        self.network.deliver(self.addr, to, buf);
+        Ok(buf.len())
+    }
+
+    async fn recv_from(
+        &self,
+        buf: &mut Vec<u8>,
+    ) -> Result<(usize, SocketAddr), GossipError> {
+        let (data, from) =
+            self.rx.lock().await.recv().await.ok_or_else(|| {
+                GossipError::NetworkError("memory network closed".to_string())
+            })?;
+
+        // truncate oversized packets the way a datagram socket would
+        let len = data.len().min(buf.len());
+        buf[..len].copy_from_slice(&data[..len]);
+        Ok((len, from))
+    }
+
+    async fn get_ip(&self) -> Result<String, GossipError> {
+        Ok(self.addr.ip().to_string())
+    }
+
+    fn supports_streams(&self) -> bool {
+        self.network.state.lock().unwrap().streams
+    }
+
+    async fn connect_stream(
+        &self,
+        addr: SocketAddr,
+    ) -> Result<Box<dyn GossipStream>, GossipError> {
+        Ok(Box::new(self.network.connect(self.addr, addr)?))
+    }
+
+    async fn accept_stream(
+        &self,
+    ) -> Result<(Box<dyn GossipStream>, SocketAddr), GossipError> {
+        let (stream, from) =
+            self.streams.lock().await.recv().await.ok_or_else(|| {
+                GossipError::NetworkError("memory network closed".to_string())
+            })?;
+
+        Ok((Box::new(stream), from))
+    }
+
+    async fn close(&self) -> Result<(), GossipError> {
+        self.network.detach(self.addr);
+        Ok(())
+    }
+}
@@ -1,45 +1,387 @@
 use std::fmt::Debug;
+use std::net::SocketAddr;
 
-use crate::constants::MAX_PAYLOAD_SIZE;
-use crate::node::Node;
-use heapless::Vec;
+use crate::constants::PROTOCOL_VERSION;
+use crate::error::GossipError;
+use crate::kv::Entry;
+use crate::node::{Node, NodeId};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
 use postcard;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
 
 #[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
 pub struct GossipMessage {
-    pub from_id: u32,
+    pub version: u8,
+    pub from_id: NodeId,
+    /// Per-origin sequence number; with `from_id` it identifies
+    /// the message across forwarding hops.
+    pub seq: u64,
     pub ttl: u8,
-    pub msg_type: String,
-    pub payload: Vec<u8, MAX_PAYLOAD_SIZE>,
+    pub kind: MessageKind,
+    pub payload: Vec<u8>,
+    /// Origin's signature; added when the message is first sent.
+    pub signature: Option<MessageSignature>,
+}
+
+/// Signature by the node a message originated from, over everything
+/// but the TTL so it survives forwarding.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MessageSignature {
+    pub public_key: VerifyingKey,
+    pub signature: Signature,
+}
+
+/// What a message carries. System kinds are handled by the protocol;
+/// `Application` messages are handed to the handler registered for
+/// their application-defined type.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MessageKind {
+    Heartbeat,
+    Update,
+    Ping,
+    PingReq,
+    Ack,
+    Leave,
+    Sync,
+    Fragment,
+    Kv,
+    /// Request for the responder registered for its type on the
+    /// node it was sent to.
+    Request(u16),
+    /// Reply to the request with the given sequence number.
+    Response(u64),
+    /// Message for the subscribers of a topic.
+    Publish,
+    Application(u16),
+}
+
+/// Unique identity of a message: its origin and sequence number.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MessageId {
+    pub origin: NodeId,
+    pub seq: u64,
+}
+
+/// Request to ping `target` and relay its ack back under `seq`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct PingReq {
+    pub seq: u32,
+    pub target: SocketAddr,
+}
+
+/// Everything a node knows, exchanged in a push-pull sync: its
+/// membership table, including its own record, and every replicated
+/// entry.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SyncState {
+    pub nodes: Vec<Node>,
+    pub entries: Vec<(String, Entry)>,
+}
+
+/// Payload of a `publish` message: the topic and the application
+/// payload, encoded with postcard.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Publication {
+    pub topic: String,
+    pub data: Vec<u8>,
+}
+
+/// One chunk of an application message too large for a datagram.
+/// Each fragment travels as its own message; receivers reassemble
+/// them by the origin and `seq` of the message they were split from.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Fragment {
+    pub seq: u64,
+    pub msg_type: u16,
+    pub index: u16,
+    pub count: u16,
+    pub data: Vec<u8>,
 }
 
 impl GossipMessage {
-    pub fn serialize(
-        msg: &GossipMessage,
-    ) -> Result<Vec<u8, MAX_PAYLOAD_SIZE>, postcard::Error> {
-        postcard::to_vec(msg)
+    /// Message of `kind` carrying `payload` encoded with postcard.
+    fn build<T: Serialize + ?Sized>(
+        id: MessageId,
+        kind: MessageKind,
+        ttl: u8,
+        payload: &T,
+    ) -> Result<GossipMessage, postcard::Error> {
+        Ok(GossipMessage {
+            version: PROTOCOL_VERSION,
+            from_id: id.origin,
+            seq: id.seq,
+            ttl,
+            kind,
+            payload: postcard::to_allocvec(payload)?,
+            signature: None,
+        })
     }
 
-    pub fn deserialize(data: &[u8]) -> Result<Self, postcard::Error> {
-        postcard::from_bytes(data)
+    /// Application message of type `msg_type` carrying `payload`.
+    /// Messages that don't fit a datagram are sent as `fragments`.
+    pub fn app<T: Serialize + ?Sized>(
+        id: MessageId,
+        msg_type: u16,
+        payload: &T,
+        ttl: Option<u8>,
+    ) -> Result<GossipMessage, GossipError> {
+        Ok(Self::build(
+            id,
+            MessageKind::Application(msg_type),
+            ttl.unwrap_or(3),
+            payload,
+        )?)
     }
 
-    pub fn heartbeat(from_id: u32, ttl: Option<u8>) -> GossipMessage {
+    /// Request of type `msg_type` carrying `payload`, sent directly
+    /// to one node with a TTL of 1 so it is never forwarded. Its
+    /// sequence number correlates the response.
+    pub fn request<T: Serialize + ?Sized>(
+        id: MessageId,
+        msg_type: u16,
+        payload: &T,
+    ) -> Result<GossipMessage, GossipError> {
+        Ok(Self::build(id, MessageKind::Request(msg_type), 1, payload)?)
+    }
+
+    /// Reply to request `seq`; `payload` is already encoded.
+    pub fn response(
+        id: MessageId,
+        seq: u64,
+        payload: Vec<u8>,
+    ) -> GossipMessage {
         GossipMessage {
-            from_id,
-            ttl: ttl.unwrap_or(3),
-            msg_type: "heartbeat".to_string(),
-            payload: postcard::to_vec("").unwrap(),
+            version: PROTOCOL_VERSION,
+            from_id: id.origin,
+            seq: id.seq,
+            ttl: 1,
+            kind: MessageKind::Response(seq),
+            payload,
+            signature: None,
         }
     }
 
-    pub fn update(node: Node, ttl: Option<u8>) -> GossipMessage {
+    /// Message publishing `payload` to the subscribers of `topic`.
+    pub fn publish<T: Serialize + ?Sized>(
+        id: MessageId,
+        topic: &str,
+        payload: &T,
+        ttl: u8,
+    ) -> Result<GossipMessage, GossipError> {
+        let publication = Publication {
+            topic: topic.to_string(),
+            data: postcard::to_allocvec(payload)?,
+        };
+        Ok(Self::build(id, MessageKind::Publish, ttl, &publication)?)
+    }
+
+    /// Application message rebuilt from the fragments of message `id`.
+    pub fn reassembled(
+        id: MessageId,
+        msg_type: u16,
+        payload: Vec<u8>,
+    ) -> GossipMessage {
         GossipMessage {
-            from_id: node.id,
-            ttl: ttl.unwrap_or(3),
-            msg_type: "update".to_string(),
-            payload: postcard::to_vec(&node).unwrap(),
+            version: PROTOCOL_VERSION,
+            from_id: id.origin,
+            seq: id.seq,
+            ttl: 0,
+            kind: MessageKind::Application(msg_type),
+            payload,
+            signature: None,
         }
     }
+
+    fn signed_fields(&self) -> Vec<u8> {
+        postcard::to_allocvec(&(
+            self.version,
+            self.from_id,
+            self.seq,
+            self.kind,
+            &self.payload,
+        ))
+        .unwrap()
+    }
+
+    /// Sign the message as its origin.
+    pub fn sign(&mut self, key: &SigningKey) {
+        self.signature = Some(MessageSignature {
+            public_key: key.verifying_key(),
+            signature: key.sign(&self.signed_fields()),
+        });
+    }
+
+    /// The key the message was validly signed with, if any.
+    pub fn verify(&self) -> Option<VerifyingKey> {
+        let signature = self.signature?;
+        signature
+            .public_key
+            .verify(&self.signed_fields(), &signature.signature)
+            .ok()?;
+        Some(signature.public_key)
+    }
+
+    /// Split an application message into chunks of `chunk_size`
+    /// bytes, each sent under an ID drawn from `next_id`. Requests,
+    /// responses and publications aren't fragmented and fail with
+    /// `PayloadTooLarge`.
+    pub fn fragments(
+        &self,
+        chunk_size: usize,
+        mut next_id: impl FnMut() -> MessageId,
+    ) -> Result<Vec<GossipMessage>, GossipError> {
+        let MessageKind::Application(msg_type) = self.kind else {
+            return Err(GossipError::PayloadTooLarge(self.payload.len()));
+        };
+
+        let chunks = self.payload.chunks(chunk_size);
+        let count = u16::try_from(chunks.len())
+            .map_err(|_| GossipError::PayloadTooLarge(self.payload.len()))?;
+
+        chunks
+            .enumerate()
+            .map(|(index, data)| {
+                let fragment = Fragment {
+                    seq: self.seq,
+                    msg_type,
+                    index: index as u16,
+                    count,
+                    data: data.to_vec(),
+                };
+                Ok(Self::build(
+                    next_id(),
+                    MessageKind::Fragment,
+                    self.ttl,
+                    &fragment,
+                )?)
+            })
+            .collect()
+    }
+
+    /// Decode the payload as a `T`.
+    pub fn decode<T: DeserializeOwned>(&self) -> Result<T, GossipError> {
+        Ok(postcard::from_bytes(&self.payload)?)
+    }
+
+    pub fn id(&self) -> MessageId {
+        MessageId {
+            origin: self.from_id,
+            seq: self.seq,
+        }
+    }
+
+    pub fn serialize(msg: &GossipMessage) -> Result<Vec<u8>, postcard::Error> {
+        postcard::to_allocvec(msg)
+    }
+
+    /// Decode a message, rejecting any sent with another wire version.
+    pub fn deserialize(data: &[u8]) -> Result<Self, GossipError> {
+        let (version, _) = postcard::take_from_bytes::<u8>(data)?;
+        if version != PROTOCOL_VERSION {
+            return Err(GossipError::UnsupportedVersion(version));
+        }
+
+        Ok(postcard::from_bytes(data)?)
+    }
+
+    pub fn heartbeat(
+        id: MessageId,
+        incarnation: u64,
+        ttl: Option<u8>,
+    ) -> GossipMessage {
+        let ttl = ttl.unwrap_or(3);
+        Self::build(id, MessageKind::Heartbeat, ttl, &incarnation).unwrap()
+    }
+
+    /// Direct probe; sent with a TTL of 1 so it is never forwarded.
+    pub fn ping(id: MessageId, seq: u32) -> GossipMessage {
+        Self::build(id, MessageKind::Ping, 1, &seq).unwrap()
+    }
+
+    pub fn ack(id: MessageId, seq: u32) -> GossipMessage {
+        Self::build(id, MessageKind::Ack, 1, &seq).unwrap()
+    }
+
+    pub fn ping_req(
+        id: MessageId,
+        seq: u32,
+        target: SocketAddr,
+    ) -> GossipMessage {
+        let req = PingReq { seq, target };
+        Self::build(id, MessageKind::PingReq, 1, &req).unwrap()
+    }
+
+    /// Departure of the origin at `incarnation`, so records of it
+    /// still in flight can't bring it back.
+    pub fn leave(
+        id: MessageId,
+        incarnation: u64,
+        ttl: Option<u8>,
+    ) -> GossipMessage {
+        Self::build(id, MessageKind::Leave, ttl.unwrap_or(3), &incarnation)
+            .unwrap()
+    }
+
+    /// Membership digest carrying a batch of node records.
+    /// Callers size batches so the message fits a datagram.
+    pub fn update(
+        id: MessageId,
+        nodes: &[Node],
+        ttl: Option<u8>,
+    ) -> GossipMessage {
+        Self::build(id, MessageKind::Update, ttl.unwrap_or(3), nodes).unwrap()
+    }
+
+    /// Batch of replicated key/value entries.
+    /// Callers size batches so the message fits a datagram.
+    pub fn kv(
+        id: MessageId,
+        entries: &[(String, Entry)],
+        ttl: u8,
+    ) -> GossipMessage {
+        Self::build(id, MessageKind::Kv, ttl, entries).unwrap()
+    }
+
+    /// Push-pull sync snapshot. Sent over a stream, so it isn't
+    /// bound by the datagram size, and never forwarded.
+    pub fn sync(id: MessageId, state: &SyncState) -> GossipMessage {
+        Self::build(id, MessageKind::Sync, 0, state).unwrap()
+    }
+
+    /// Decode the incarnation carried by a `heartbeat` or `leave`
+    /// message.
+    pub fn incarnation(&self) -> Result<u64, GossipError> {
+        self.decode()
+    }
+
+    /// Decode the sequence number carried by a `ping` or `ack` message.
+    pub fn probe_seq(&self) -> Result<u32, GossipError> {
+        self.decode()
+    }
+
+    pub fn ping_request(&self) -> Result<PingReq, GossipError> {
+        self.decode()
+    }
+
+    /// Decode the node records carried by an `update` message.
+    pub fn nodes(&self) -> Result<Vec<Node>, GossipError> {
+        self.decode()
+    }
+
+    pub fn fragment(&self) -> Result<Fragment, GossipError> {
+        self.decode()
+    }
+
+    pub fn sync_state(&self) -> Result<SyncState, GossipError> {
+        self.decode()
+    }
+
+    pub fn publication(&self) -> Result<Publication, GossipError> {
+        self.decode()
+    }
+
+    /// Decode the key/value entries carried by a `kv` message.
+    pub fn entries(&self) -> Result<Vec<(String, Entry)>, GossipError> {
+        self.decode()
+    }
 }
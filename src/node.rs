@@ -1,33 +1,148 @@
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::net::SocketAddr;
 use std::time::{Duration, SystemTime};
 
+/// 128-bit node identifier derived from the node's name.
+#[derive(
+    Serialize,
+    Deserialize,
+    Clone,
+    Copy,
+    Debug,
+    PartialEq,
+    Eq,
+    Hash,
+    PartialOrd,
+    Ord,
+)]
+pub struct NodeId(pub u128);
+
+impl fmt::Display for NodeId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:032x}", self.0)
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Hash)]
 pub enum NodeStatus {
     Online,
+    Suspect,
     Offline,
+    Left,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, Hash)]
+impl NodeStatus {
+    /// Order in which states override each other at equal incarnation.
+    fn precedence(&self) -> u8 {
+        match self {
+            NodeStatus::Online => 0,
+            NodeStatus::Suspect => 1,
+            NodeStatus::Offline => 2,
+            NodeStatus::Left => 3,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Node {
-    pub id: u32,
+    pub id: NodeId,
     pub addr: SocketAddr,
     pub status: NodeStatus,
     pub last_heartbeat: SystemTime,
+    /// Bumped by the node itself to refute suspicion.
+    pub incarnation: u64,
+    /// The node's signing identity; unknown for unverified seeds.
+    pub public_key: Option<VerifyingKey>,
+    /// Application-defined key/value pairs, e.g. role or region.
+    pub metadata: BTreeMap<String, String>,
+    pub tags: BTreeSet<String>,
+    /// Topics the node subscribes to; publications are sent to
+    /// subscribers first.
+    pub topics: BTreeSet<String>,
+    /// Bumped by the node whenever its metadata, tags or topics change.
+    pub meta_version: u64,
+    /// The node's own signature over its address, incarnation, key and
+    /// metadata, letting peers relay the record without forging it.
+    pub signature: Option<Signature>,
 }
 
 impl Node {
-    pub fn new(id: u32, addr: SocketAddr) -> Self {
+    pub fn new(id: NodeId, addr: SocketAddr) -> Self {
         Node {
             id,
             addr,
             status: NodeStatus::Online,
             last_heartbeat: SystemTime::now(),
+            incarnation: 0,
+            public_key: None,
+            metadata: BTreeMap::new(),
+            tags: BTreeSet::new(),
+            topics: BTreeSet::new(),
+            meta_version: 0,
+            signature: None,
         }
     }
 
+    /// Encoded size of the node's metadata and tags.
+    pub fn metadata_size(&self) -> usize {
+        postcard::to_allocvec(&(&self.metadata, &self.tags))
+            .map(|buf| buf.len())
+            .unwrap_or(usize::MAX)
+    }
+
+    /// Encoded size of the topics the node subscribes to.
+    pub fn topics_size(&self) -> usize {
+        postcard::to_allocvec(&self.topics)
+            .map(|buf| buf.len())
+            .unwrap_or(usize::MAX)
+    }
+
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.contains(tag)
+    }
+
+    pub fn subscribes_to(&self, topic: &str) -> bool {
+        self.topics.contains(topic)
+    }
+
+    /// Fields covered by the owner's signature. Status is left out so
+    /// peers can still relay suspicion at the signed incarnation.
+    fn signed_fields(&self) -> Vec<u8> {
+        postcard::to_allocvec(&(
+            self.id,
+            self.addr,
+            self.incarnation,
+            self.public_key,
+            &self.metadata,
+            &self.tags,
+            &self.topics,
+            self.meta_version,
+        ))
+        .unwrap()
+    }
+
+    /// Sign the record as its owner.
+    pub fn sign(&mut self, key: &SigningKey) {
+        self.public_key = Some(key.verifying_key());
+        self.signature = Some(key.sign(&self.signed_fields()));
+    }
+
+    /// Whether the record carries a valid signature from its owner.
+    pub fn verify(&self) -> bool {
+        let (Some(key), Some(signature)) = (self.public_key, self.signature)
+        else {
+            return false;
+        };
+
+        key.verify(&self.signed_fields(), &signature).is_ok()
+    }
+
     pub fn is_offline(&self, timeout: Duration) -> bool {
-        matches!(self.status, NodeStatus::Offline)
+        matches!(self.status, NodeStatus::Offline | NodeStatus::Left)
             || SystemTime::now()
                 .duration_since(self.last_heartbeat)
                 .unwrap_or(Duration::from_secs(0))
@@ -38,10 +153,81 @@ impl Node {
         self.last_heartbeat = SystemTime::now();
         self.status = NodeStatus::Online;
     }
+
+    pub fn has_left(&self) -> bool {
+        matches!(self.status, NodeStatus::Left)
+    }
+
+    /// Mark the node as having announced its departure.
+    pub fn leave(&mut self) {
+        self.last_heartbeat = SystemTime::now();
+        self.status = NodeStatus::Left;
+    }
+
+    /// The key messages from this node must be signed with. Keys are
+    /// trusted on first use; a node that went offline or left may come
+    /// back with a new identity.
+    pub fn pinned_key(&self) -> Option<VerifyingKey> {
+        match self.status {
+            NodeStatus::Online | NodeStatus::Suspect => self.public_key,
+            NodeStatus::Offline | NodeStatus::Left => None,
+        }
+    }
+
+    /// Whether `self` should replace `current`. A higher incarnation
+    /// always wins; at equal incarnation the more severe status wins,
+    /// so suspicion overrides `Online` until the node refutes it.
+    pub fn supersedes(&self, current: &Node) -> bool {
+        self.incarnation > current.incarnation
+            || (self.incarnation == current.incarnation
+                && self.status.precedence() > current.status.precedence())
+    }
+
+    /// Take the fields `other` was signed with, other than the
+    /// incarnation: address, key, metadata, topics and the signature
+    /// itself.
+    pub fn adopt(&mut self, other: &Node) {
+        self.addr = other.addr;
+        self.public_key = other.public_key;
+        self.metadata = other.metadata.clone();
+        self.tags = other.tags.clone();
+        self.topics = other.topics.clone();
+        self.meta_version = other.meta_version;
+        self.signature = other.signature;
+    }
+
+    /// Take the state of `other` if it supersedes ours.
+    /// Peers' clocks aren't trusted, so a record that brings the node
+    /// back `Online` counts as a heartbeat observed now.
+    /// Returns true if the record changed.
+    pub fn merge(&mut self, other: &Node) -> bool {
+        if !other.supersedes(self) {
+            return false;
+        }
+
+        // at equal incarnation only the status changes; our signed
+        // fields may be newer than the ones the suspicion was relayed with
+        if other.incarnation > self.incarnation {
+            self.adopt(other);
+        }
+        self.status = other.status.clone();
+        self.incarnation = other.incarnation;
+        if matches!(self.status, NodeStatus::Online) {
+            self.last_heartbeat = SystemTime::now();
+        }
+        true
+    }
 }
 
 impl Eq for Node {}
 
+impl Hash for Node {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+        self.addr.hash(state);
+    }
+}
+
 impl PartialEq for Node {
     fn eq(&self, other: &Self) -> bool {
         self.id == other.id && self.addr == other.addr
@@ -1,76 +1,1125 @@
-use std::collections::HashMap;
-use std::{net::SocketAddr, time::Duration};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::{
+    net::SocketAddr,
+    time::{Duration, Instant, SystemTime},
+};
 
-use crate::constants::MAX_PAYLOAD_SIZE;
+use crate::constants::{
+    MAX_FRAME_SIZE, MAX_METADATA_SIZE, MESSAGE_OVERHEAD, NODE_RECORD_OVERHEAD,
+    REASSEMBLY_CAPACITY, SEEN_CACHE_CAPACITY, SEEN_CACHE_WINDOW,
+    STREAM_TIMEOUT, SUBSCRIBER_CAPACITY,
+};
+use crate::discovery::Discovery;
 use crate::error::GossipError;
+use crate::event::MembershipEvent;
+use crate::fragment::Reassembler;
+use crate::handler::Handlers;
+use crate::keyring::{ENCRYPTION_OVERHEAD, KEY_SIZE, Keyring};
+use crate::kv::{Entry, KvEvent, KvStore};
 // use crate::message::GossipMessage;
-use crate::node::Node;
+use crate::message::{
+    Fragment, MessageId, MessageKind, Publication, SyncState,
+};
+use crate::node::{Node, NodeId, NodeStatus};
+use crate::retry::retry;
+use crate::seen::SeenCache;
+use crate::stream::{GossipStream, read_frame, write_frame};
+use crate::topic::{Subscriptions, TopicMessage};
 use crate::{config::GossipConfig, message::GossipMessage};
 use async_trait::async_trait;
+use ed25519_dalek::{SigningKey, VerifyingKey};
 
 use log::{debug, error, info};
+use serde::Serialize;
 
-use rand::{SeedableRng, rngs::StdRng, seq::index::sample};
+use rand::{Rng, SeedableRng, rngs::StdRng, seq::index::sample};
 
 use tokio::{
-    sync::{Mutex, RwLock},
-    time::{interval, sleep},
+    sync::{Mutex, RwLock, broadcast, oneshot, watch},
+    time::{interval, sleep, timeout},
 };
 
+/// Who is waiting on the ack for an outstanding ping.
+enum PendingAck {
+    /// One of our own probes.
+    Probe(oneshot::Sender<()>),
+    /// A ping we sent on behalf of a peer's `ping-req`;
+    /// the ack is relayed to `requester` under its original sequence.
+    Relay { requester: SocketAddr, seq: u32 },
+}
+
 pub struct GossipProtocol {
     config: GossipConfig,
-    local_node: Node,
-    nodes: RwLock<HashMap<u32, Node>>,
+    local_id: NodeId,
+    /// Our record; metadata, tags and topics can change at runtime.
+    local_node: std::sync::RwLock<Node>,
+    nodes: RwLock<HashMap<NodeId, Node>>,
     transport: Box<dyn GossipTransport>,
+    handlers: Handlers,
+    messages: broadcast::Sender<GossipMessage>,
+    events: broadcast::Sender<MembershipEvent>,
+    shutdown: watch::Sender<bool>,
     rng: Mutex<StdRng>,
+    /// Our incarnation; bumped to refute suspicion about ourselves.
+    incarnation: AtomicU64,
+    /// Set once we've announced our departure, so syncs still in
+    /// flight don't make us refute it.
+    leaving: AtomicBool,
+    probe_seq: AtomicU32,
+    /// Sequence number for the next message we originate. Starts at
+    /// a random point so a node restarted under the same name doesn't
+    /// reuse IDs its peers still remember as seen.
+    msg_seq: AtomicU64,
+    /// Messages already handled, so forwarded copies are dropped.
+    seen: Mutex<SeenCache>,
+    /// Identity our messages and node record are signed with.
+    signing_key: SigningKey,
+    /// Cluster keys every datagram is sealed and opened with.
+    keyring: std::sync::RwLock<Option<Keyring>>,
+    /// Packets dropped because they failed authentication.
+    unauthenticated: AtomicU64,
+    /// Fragments of large messages still being reassembled.
+    fragments: Mutex<Reassembler>,
+    /// Replicated key/value entries.
+    kv: Mutex<KvStore>,
+    kv_events: broadcast::Sender<KvEvent>,
+    /// Topics we subscribe to, each with its own channel.
+    subscriptions: Mutex<Subscriptions>,
+    pending_acks: Mutex<HashMap<u32, (PendingAck, Instant)>>,
+    /// Requests awaiting a response, by sequence number, with the
+    /// node they were sent to.
+    pending_requests:
+        Mutex<HashMap<u64, (NodeId, oneshot::Sender<GossipMessage>)>>,
+    /// When each currently suspected node was first suspected.
+    suspects: Mutex<HashMap<NodeId, Instant>>,
 }
 
 impl GossipProtocol {
     pub fn new(
         config: GossipConfig,
-        local_node: Node,
-        seed_peers: HashMap<u32, Node>,
+        mut local_node: Node,
+        seed_peers: HashMap<NodeId, Node>,
         transport: Box<dyn GossipTransport>,
+        handlers: Handlers,
     ) -> Self {
+        let signing_key = config
+            .signing_key
+            .clone()
+            .unwrap_or_else(|| SigningKey::from_bytes(&rand::rng().random()));
+        local_node.public_key = Some(signing_key.verifying_key());
+
+        let fragments = Reassembler::new(
+            REASSEMBLY_CAPACITY,
+            config.reassembly_timeout,
+            config.max_message_size,
+        );
+
+        let keyring = config.keyring.clone();
+        let kv = KvStore::new(local_node.id);
+
         GossipProtocol {
             config,
-            local_node,
+            local_id: local_node.id,
+            local_node: std::sync::RwLock::new(local_node),
             nodes: RwLock::new(seed_peers),
             transport,
+            handlers,
+            messages: broadcast::channel(SUBSCRIBER_CAPACITY).0,
+            events: broadcast::channel(SUBSCRIBER_CAPACITY).0,
+            shutdown: watch::channel(false).0,
             rng: Mutex::new(StdRng::from_os_rng()),
+            incarnation: AtomicU64::new(0),
+            leaving: AtomicBool::new(false),
+            probe_seq: AtomicU32::new(0),
+            msg_seq: AtomicU64::new(rand::rng().random::<u32>() as u64),
+            seen: Mutex::new(SeenCache::new(
+                SEEN_CACHE_CAPACITY,
+                SEEN_CACHE_WINDOW,
+            )),
+            signing_key,
+            keyring: std::sync::RwLock::new(keyring),
+            unauthenticated: AtomicU64::new(0),
+            fragments: Mutex::new(fragments),
+            kv: Mutex::new(kv),
+            kv_events: broadcast::channel(SUBSCRIBER_CAPACITY).0,
+            subscriptions: Mutex::new(Subscriptions::new()),
+            pending_acks: Mutex::new(HashMap::new()),
+            pending_requests: Mutex::new(HashMap::new()),
+            suspects: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The local node's record as advertised to peers.
+    pub fn local_node(&self) -> Node {
+        let mut local = self.local_node.read().unwrap().clone();
+        local.incarnation = self.incarnation.load(Ordering::SeqCst);
+        local.update_heartbeat();
+        local.sign(&self.signing_key);
+        local
+    }
+
+    /// Change our metadata, tags or topics and tell the cluster
+    /// straight away.
+    /// The change is rejected if metadata and tags exceed
+    /// `MAX_METADATA_SIZE`, or if the record no longer fits a datagram
+    /// once topics are added.
+    pub async fn update_local(
+        &self,
+        f: impl FnOnce(&mut Node),
+    ) -> Result<(), GossipError> {
+        {
+            let mut local = self.local_node.write().unwrap();
+            let mut updated = local.clone();
+            f(&mut updated);
+
+            let size = updated.metadata_size();
+            if size > MAX_METADATA_SIZE {
+                return Err(GossipError::PayloadTooLarge(size));
+            }
+
+            let size = size + updated.topics_size();
+            if !updated.topics.is_empty() && size > self.record_budget() {
+                return Err(GossipError::PayloadTooLarge(size));
+            }
+
+            updated.meta_version += 1;
+            *local = updated;
+        }
+
+        self.announce().await
+    }
+
+    /// Peers subscribed to `topic`, excluding the local node.
+    pub async fn subscribers(&self, topic: &str) -> Vec<Node> {
+        self.nodes
+            .read()
+            .await
+            .values()
+            .filter(|n| n.id != self.local_id && n.subscribes_to(topic))
+            .cloned()
+            .collect()
+    }
+
+    /// Peers carrying `tag`, excluding the local node.
+    pub async fn peers_with_tag(&self, tag: &str) -> Vec<Node> {
+        self.nodes
+            .read()
+            .await
+            .values()
+            .filter(|n| n.id != self.local_id && n.has_tag(tag))
+            .cloned()
+            .collect()
+    }
+
+    /// Snapshot of every known peer, excluding the local node.
+    pub async fn peers(&self) -> Vec<Node> {
+        self.nodes
+            .read()
+            .await
+            .values()
+            .filter(|n| n.id != self.local_id)
+            .cloned()
+            .collect()
+    }
+
+    /// ID for the next message originated by this node.
+    fn next_id(&self) -> MessageId {
+        MessageId {
+            origin: self.local_id,
+            seq: self.msg_seq.fetch_add(1, Ordering::SeqCst),
+        }
+    }
+
+    /// Gossip an application message to the cluster.
+    /// Messages too large for a datagram are sent over streams, or
+    /// split into fragments all sent to the same peers if the
+    /// transport has none; payloads above `max_message_size` are
+    /// rejected.
+    pub async fn broadcast<T: Serialize + ?Sized>(
+        &self,
+        msg_type: u16,
+        payload: &T,
+    ) -> Result<(), GossipError> {
+        let msg = GossipMessage::app(
+            self.next_id(),
+            msg_type,
+            payload,
+            Some(self.config.message_ttl),
+        )?;
+
+        if msg.payload.len() > self.config.max_message_size {
+            return Err(GossipError::PayloadTooLarge(msg.payload.len()));
+        }
+
+        if msg.payload.len() <= self.payload_budget()
+            || self.transport.supports_streams()
+        {
+            return self.gossip(msg, None).await;
+        }
+
+        let fragments =
+            msg.fragments(self.payload_budget(), || self.next_id())?;
+        let addresses = self.gossip_addresses(None, None).await;
+
+        for fragment in &fragments {
+            for addr in &addresses {
+                self.send(fragment, *addr).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Publish a message to the subscribers of `topic`. It is gossiped
+    /// to subscribed peers first, while other peers still relay it
+    /// within the TTL. Publications must fit a datagram unless the
+    /// transport has streams.
+    pub async fn publish<T: Serialize + ?Sized>(
+        &self,
+        topic: &str,
+        payload: &T,
+    ) -> Result<(), GossipError> {
+        let msg = GossipMessage::publish(
+            self.next_id(),
+            topic,
+            payload,
+            self.config.message_ttl,
+        )?;
+
+        if msg.payload.len() > self.config.max_message_size {
+            return Err(GossipError::PayloadTooLarge(msg.payload.len()));
+        }
+
+        self.gossip(msg, None).await
+    }
+
+    /// Subscribe to `topic` and advertise it in our record.
+    pub async fn subscribe_topic(
+        &self,
+        topic: String,
+    ) -> Result<broadcast::Receiver<TopicMessage>, GossipError> {
+        let (new, rx) = self.subscriptions.lock().await.subscribe(&topic);
+        if !new {
+            return Ok(rx);
+        }
+
+        let advertised = self
+            .update_local(|node| {
+                node.topics.insert(topic.clone());
+            })
+            .await;
+
+        // keep the subscription if only the announcement failed
+        if let Err(e) = advertised {
+            if !self.local_node.read().unwrap().subscribes_to(&topic) {
+                self.subscriptions.lock().await.unsubscribe(&topic);
+            }
+            return Err(e);
+        }
+
+        Ok(rx)
+    }
+
+    /// Drop our subscription to `topic`, closing its receivers, and
+    /// stop advertising it.
+    pub async fn unsubscribe_topic(
+        &self,
+        topic: &str,
+    ) -> Result<(), GossipError> {
+        if !self.subscriptions.lock().await.unsubscribe(topic) {
+            return Ok(());
+        }
+
+        self.update_local(|node| {
+            node.topics.remove(topic);
+        })
+        .await
+    }
+
+    /// Send an application message to a single node. It is delivered
+    /// like a broadcast but never forwarded.
+    pub async fn send_to<T: Serialize + ?Sized>(
+        &self,
+        node_id: NodeId,
+        msg_type: u16,
+        payload: &T,
+    ) -> Result<(), GossipError> {
+        let msg =
+            GossipMessage::app(self.next_id(), msg_type, payload, Some(1))?;
+        let addr = self.address_of(node_id).await?;
+        self.unicast(&msg, addr).await
+    }
+
+    /// Send a request to a single node and wait up to `wait` for the
+    /// reply of the responder it has registered for `msg_type`. Without
+    /// streams, requests and replies must fit a datagram.
+    pub async fn request<T: Serialize + ?Sized>(
+        &self,
+        node_id: NodeId,
+        msg_type: u16,
+        payload: &T,
+        wait: Duration,
+    ) -> Result<GossipMessage, GossipError> {
+        let msg = GossipMessage::request(self.next_id(), msg_type, payload)?;
+        let addr = self.address_of(node_id).await?;
+
+        let (tx, rx) = oneshot::channel();
+        {
+            let mut pending = self.pending_requests.lock().await;
+            // requests whose caller gave up waiting
+            pending.retain(|_, (_, tx)| !tx.is_closed());
+            pending.insert(msg.seq, (node_id, tx));
+        }
+
+        if let Err(e) = self.unicast(&msg, addr).await {
+            self.pending_requests.lock().await.remove(&msg.seq);
+            return Err(e);
+        }
+
+        match timeout(wait, rx).await {
+            Ok(Ok(reply)) => Ok(reply),
+            _ => {
+                self.pending_requests.lock().await.remove(&msg.seq);
+                Err(GossipError::Timeout(format!(
+                    "no response from {} within {:?}",
+                    node_id, wait
+                )))
+            }
+        }
+    }
+
+    /// Address of a known node.
+    async fn address_of(
+        &self,
+        node_id: NodeId,
+    ) -> Result<SocketAddr, GossipError> {
+        self.nodes
+            .read()
+            .await
+            .get(&node_id)
+            .map(|n| n.addr)
+            .ok_or_else(|| {
+                GossipError::PeerError(format!("unknown node {}", node_id))
+            })
+    }
+
+    /// Bytes of payload that fit a datagram alongside the headers.
+    fn payload_budget(&self) -> usize {
+        let budget = self.config.max_datagram_size - MESSAGE_OVERHEAD;
+        if self.keyring.read().unwrap().is_some() {
+            budget - ENCRYPTION_OVERHEAD
+        } else {
+            budget
         }
     }
 
+    /// Bytes of metadata, tags and topics our record can carry and
+    /// still fit a datagram of `max_datagram_size`. Peers with a
+    /// smaller `max_datagram_size` only get a record this large over
+    /// streams.
+    fn record_budget(&self) -> usize {
+        self.payload_budget()
+            .saturating_sub(NODE_RECORD_OVERHEAD)
+            .max(MAX_METADATA_SIZE)
+    }
+
+    /// Add a cluster key that incoming packets may be sealed with.
+    pub fn install_key(&self, key: [u8; KEY_SIZE]) -> Result<(), GossipError> {
+        self.with_keyring(|keyring| {
+            keyring.install(key);
+            Ok(())
+        })
+    }
+
+    /// Start sealing outgoing packets with an installed key.
+    pub fn use_key(&self, key: [u8; KEY_SIZE]) -> Result<(), GossipError> {
+        self.with_keyring(|keyring| keyring.use_key(key))
+    }
+
+    /// Stop accepting packets sealed with a retired key.
+    pub fn remove_key(&self, key: [u8; KEY_SIZE]) -> Result<(), GossipError> {
+        self.with_keyring(|keyring| keyring.remove(key))
+    }
+
+    /// Encryption can't be switched on at runtime: every node would
+    /// have to do so at once, so the keyring must be configured.
+    fn with_keyring(
+        &self,
+        f: impl FnOnce(&mut Keyring) -> Result<(), GossipError>,
+    ) -> Result<(), GossipError> {
+        match self.keyring.write().unwrap().as_mut() {
+            Some(keyring) => f(keyring),
+            None => Err(GossipError::KeyringError(
+                "encryption is not enabled".to_string(),
+            )),
+        }
+    }
+
+    /// Number of packets dropped because they failed authentication.
+    pub fn unauthenticated_packets(&self) -> u64 {
+        self.unauthenticated.load(Ordering::Relaxed)
+    }
+
+    /// Current value of `key` in the replicated store, if it is set
+    /// and not deleted.
+    pub async fn get(&self, key: &str) -> Option<Vec<u8>> {
+        self.kv.lock().await.get(key).map(<[u8]>::to_vec)
+    }
+
+    /// Write a key, or delete it if `value` is `None`, and gossip the
+    /// entry. Entries must fit a single datagram.
+    pub async fn set(
+        &self,
+        key: String,
+        value: Option<Vec<u8>>,
+    ) -> Result<(), GossipError> {
+        let entry = self.kv.lock().await.write(value);
+
+        let msg = GossipMessage::kv(
+            self.next_id(),
+            &[(key.clone(), entry.clone())],
+            self.config.message_ttl,
+        );
+        if msg.payload.len() > self.payload_budget() {
+            return Err(GossipError::PayloadTooLarge(msg.payload.len()));
+        }
+
+        self.merge_entries(vec![(key, entry)]).await;
+        self.gossip(msg, None).await
+    }
+
+    /// Receive changes to replicated keys.
+    pub fn watch(&self) -> broadcast::Receiver<KvEvent> {
+        self.kv_events.subscribe()
+    }
+
+    /// Keep entries newer than ours and notify watchers of them.
+    async fn merge_entries(&self, entries: Vec<(String, Entry)>) {
+        let mut kv = self.kv.lock().await;
+        for (key, entry) in entries {
+            let value = entry.value.clone();
+            if kv.merge(&key, entry) {
+                let _ = self.kv_events.send(KvEvent { key, value });
+            }
+        }
+    }
+
+    /// Receive every application message delivered to this node.
+    pub fn subscribe(&self) -> broadcast::Receiver<GossipMessage> {
+        self.messages.subscribe()
+    }
+
+    /// Receive membership changes as they are observed.
+    pub fn events(&self) -> broadcast::Receiver<MembershipEvent> {
+        self.events.subscribe()
+    }
+
+    /// Publish a membership event; dropped if nobody is listening.
+    fn emit(&self, event: MembershipEvent) {
+        let _ = self.events.send(event);
+    }
+
+    /// Tell peers we are leaving so they can mark us `Left`
+    /// without waiting for `offline_timeout`.
+    pub async fn leave(&self) -> Result<(), GossipError> {
+        self.leaving.store(true, Ordering::SeqCst);
+        let msg = GossipMessage::leave(
+            self.next_id(),
+            self.incarnation.load(Ordering::SeqCst),
+            Some(self.config.message_ttl),
+        );
+
+        self.gossip(msg, None).await
+    }
+
+    /// Signal the protocol loops to return.
+    pub fn stop(&self) {
+        self.shutdown.send_replace(true);
+    }
+
+    pub async fn close(&self) -> Result<(), GossipError> {
+        self.transport.close().await
+    }
+
     pub async fn start_heartbeat(&self) {
         let mut interval = interval(self.config.heartbeat_interval);
+        let mut shutdown = self.shutdown.subscribe();
 
         loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = shutdown.wait_for(|stop| *stop) => break,
+            }
+
             let msg = GossipMessage::heartbeat(
-                self.local_node.id,
+                self.next_id(),
+                self.incarnation.load(Ordering::SeqCst),
                 Some(self.config.message_ttl),
             );
 
             if let Err(e) = self.gossip(msg, None).await {
                 error!("Error sending heartbeat: {}", e);
             }
+        }
+    }
+
+    /// Start the membership gossip loop.
+    /// Every `gossip_interval` the local view of the cluster is sent
+    /// to `fanout` peers as a series of `update` messages, followed by
+    /// every replicated entry so peers that missed a write catch up.
+    pub async fn start_gossip(&self) {
+        let mut interval = interval(self.config.gossip_interval);
+        let mut shutdown = self.shutdown.subscribe();
+
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = shutdown.wait_for(|stop| *stop) => break,
+            }
+
+            for msg in self.update_messages().await {
+                if let Err(e) = self.gossip(msg, None).await {
+                    error!("Error sending update: {}", e);
+                }
+            }
+
+            for msg in self.kv_messages().await {
+                if let Err(e) = self.gossip(msg, None).await {
+                    error!("Error sending entries: {}", e);
+                }
+            }
+        }
+    }
+
+    /// Start the push-pull sync loop.
+    /// Every `gossip_interval` our membership table and replicated
+    /// entries are exchanged in full with one random peer over a
+    /// stream, repairing any updates either side missed. Only runs if
+    /// the transport has streams.
+    pub async fn start_sync(&self) {
+        if !self.transport.supports_streams() {
+            info!("transport has no streams, push-pull sync disabled");
+            return;
+        }
+
+        let mut interval = interval(self.config.gossip_interval);
+        let mut shutdown = self.shutdown.subscribe();
+
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = shutdown.wait_for(|stop| *stop) => break,
+            }
+
+            let Some(addr) =
+                self.gossip_addresses(None, None).await.first().copied()
+            else {
+                continue;
+            };
+
+            let synced = tokio::select! {
+                synced = timeout(STREAM_TIMEOUT, self.sync_with(addr)) => synced,
+                _ = shutdown.wait_for(|stop| *stop) => break,
+            };
+
+            match synced {
+                Ok(Ok(())) => debug!("synced with {}", addr),
+                Ok(Err(e)) => error!("Error syncing with {}: {}", addr, e),
+                Err(_) => error!("Sync with {} timed out", addr),
+            }
+        }
+    }
+
+    /// Start accepting streams opened by peers, handling each on its
+    /// own task. Only runs if the transport has streams.
+    pub async fn start_streams(self: Arc<Self>) {
+        if !self.transport.supports_streams() {
+            return;
+        }
+
+        let mut shutdown = self.shutdown.subscribe();
+
+        loop {
+            let accepted = tokio::select! {
+                biased;
+                _ = shutdown.wait_for(|stop| *stop) => break,
+                accepted = self.transport.accept_stream() => accepted,
+            };
+
+            match accepted {
+                Ok((stream, src)) => {
+                    let p = Arc::clone(&self);
+                    tokio::spawn(async move {
+                        match timeout(STREAM_TIMEOUT, p.handle_stream(stream))
+                            .await
+                        {
+                            Ok(Ok(())) => {}
+                            Ok(Err(e)) => {
+                                error!("Error on stream from {}: {}", src, e)
+                            }
+                            Err(_) => error!("Stream from {} timed out", src),
+                        }
+                    });
+                }
+                Err(e) => {
+                    error!("Error accepting stream: {}", e);
+                    sleep(self.config.probe_interval).await;
+                }
+            }
+        }
+    }
+
+    /// Handle the message a peer opened a stream with: a sync to
+    /// answer, or an application message, request or response too
+    /// large for a datagram. The latter come after an update with the
+    /// signed records of the peer and the message's origin, as the
+    /// stream's source address isn't one either gossips from.
+    async fn handle_stream(
+        self: &Arc<Self>,
+        mut stream: Box<dyn GossipStream>,
+    ) -> Result<(), GossipError> {
+        let mut msg = self.read_message(stream.as_mut()).await?;
+        if msg.kind == MessageKind::Update {
+            self.merge_nodes(msg.from_id, msg.nodes()?).await;
+            msg = self.read_message(stream.as_mut()).await?;
+        }
+
+        match msg.kind {
+            MessageKind::Sync => {
+                let state = msg.sync_state()?;
+
+                let reply = GossipMessage::sync(
+                    self.next_id(),
+                    &self.sync_state().await,
+                );
+                write_frame(stream.as_mut(), &self.seal(&reply)?).await?;
+
+                self.merge_sync(msg.from_id, state).await;
+            }
+            MessageKind::Application(_)
+            | MessageKind::Request(_)
+            | MessageKind::Response(_)
+            | MessageKind::Publish => {
+                if msg.payload.len() > self.config.max_message_size {
+                    return Err(GossipError::PayloadTooLarge(
+                        msg.payload.len(),
+                    ));
+                }
+
+                // left unseen, so a copy arriving once we know its
+                // origin is still delivered
+                if !self.nodes.read().await.contains_key(&msg.from_id) {
+                    return Err(GossipError::PeerError(format!(
+                        "message from unknown node {}",
+                        msg.from_id
+                    )));
+                }
+
+                // a copy we already handled and forwarded
+                if !self.seen.lock().await.insert(msg.id()) {
+                    return Ok(());
+                }
+
+                self.deliver(&msg).await;
+                self.forward(msg).await;
+            }
+            kind => {
+                return Err(GossipError::PeerError(format!(
+                    "unexpected {:?} message on a stream",
+                    kind
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Send our state to the node at `addr` and merge its reply.
+    async fn sync_with(&self, addr: SocketAddr) -> Result<(), GossipError> {
+        let mut stream = self.transport.connect_stream(addr).await?;
+
+        let msg = GossipMessage::sync(self.next_id(), &self.sync_state().await);
+        write_frame(stream.as_mut(), &self.seal(&msg)?).await?;
+
+        let reply = self.read_message(stream.as_mut()).await?;
+        if reply.kind != MessageKind::Sync {
+            return Err(GossipError::PeerError(format!(
+                "unexpected {:?} reply to a sync",
+                reply.kind
+            )));
+        }
+
+        self.merge_sync(reply.from_id, reply.sync_state()?).await;
+        Ok(())
+    }
+
+    /// Our membership table, including our own record, and entries.
+    async fn sync_state(&self) -> SyncState {
+        let mut nodes = vec![self.local_node()];
+        nodes.extend(self.nodes.read().await.values().cloned());
+
+        let entries = self
+            .kv
+            .lock()
+            .await
+            .entries()
+            .map(|(k, e)| (k.clone(), e.clone()))
+            .collect();
+
+        SyncState { nodes, entries }
+    }
+
+    /// Read, decrypt and authenticate a message from a stream. Unlike a
+    /// datagram's, its source address says nothing about where the
+    /// sender gossips from, so the sender is only learned from records.
+    async fn read_message(
+        &self,
+        stream: &mut dyn GossipStream,
+    ) -> Result<GossipMessage, GossipError> {
+        let frame = read_frame(stream, MAX_FRAME_SIZE).await?;
+
+        let Some(data) = self.open(&frame) else {
+            self.unauthenticated.fetch_add(1, Ordering::Relaxed);
+            return Err(GossipError::PeerError(
+                "unauthenticated stream".to_string(),
+            ));
+        };
+
+        let msg = GossipMessage::deserialize(&data)?;
+        if msg.from_id == self.local_id {
+            return Err(GossipError::PeerError(
+                "our own message on a stream".to_string(),
+            ));
+        }
+
+        if self.authenticate(&msg).await.is_none() {
+            self.unauthenticated.fetch_add(1, Ordering::Relaxed);
+            return Err(GossipError::PeerError(format!(
+                "unverified message from {}",
+                msg.from_id
+            )));
+        }
+
+        Ok(msg)
+    }
+
+    async fn merge_sync(&self, from_id: NodeId, state: SyncState) {
+        self.merge_nodes(from_id, state.nodes).await;
+        self.merge_entries(state.entries).await;
+    }
+
+    /// Start the SWIM failure detector.
+    /// Every `probe_interval` a random peer is pinged directly; if it
+    /// doesn't ack within `probe_timeout`, `indirect_probes` other peers
+    /// are asked to ping it on our behalf. A peer that answers neither
+    /// is marked `Suspect`, and declared `Offline` if it doesn't refute
+    /// the suspicion within `suspicion_timeout`.
+    pub async fn start_probe(&self) {
+        let mut interval = interval(self.config.probe_interval);
+        let mut shutdown = self.shutdown.subscribe();
+
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = shutdown.wait_for(|stop| *stop) => break,
+            }
+
+            self.expire_suspects().await;
+            self.expire_pending_acks().await;
+
+            let Some(target) = self.probe_target().await else {
+                continue;
+            };
+
+            if !self.probe(&target).await {
+                self.suspect(target.id).await;
+            }
+        }
+    }
+
+    /// Start polling a discovery source every `discovery_interval`.
+    /// Errors are retried with backoff before the poll is skipped.
+    pub async fn start_discovery(&self, discovery: Box<dyn Discovery>) {
+        let mut interval = interval(self.config.discovery_interval);
+        let mut shutdown = self.shutdown.subscribe();
+        let mut known = HashSet::new();
+
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = shutdown.wait_for(|stop| *stop) => break,
+            }
+
+            let addrs = tokio::select! {
+                addrs = retry(|| discovery.discover(), None, None) => addrs,
+                _ = shutdown.wait_for(|stop| *stop) => break,
+            };
+
+            match addrs {
+                Ok(addrs) => self.discovered(&mut known, addrs).await,
+                Err(e) => error!("Error discovering peers: {}", e),
+            }
+        }
+    }
+
+    /// Send our record to discovered addresses no known node is at,
+    /// so they add us and gossip back, and probe nodes at addresses
+    /// that were discovered before but have since gone. A node that
+    /// doesn't answer is suspected straight away rather than when it
+    /// is next picked at random; one that does stays online, as a
+    /// source can briefly drop a live node, e.g. on a DNS timeout or
+    /// a partially written file.
+    async fn discovered(
+        &self,
+        known: &mut HashSet<SocketAddr>,
+        addrs: Vec<SocketAddr>,
+    ) {
+        let local_addr = self.local_node.read().unwrap().addr;
+        let current = addrs
+            .into_iter()
+            .filter(|addr| *addr != local_addr)
+            .collect::<HashSet<_>>();
+
+        let (unknown, gone) = {
+            let nodes = self.nodes.read().await;
+            let at = |addr: &SocketAddr| {
+                nodes.values().find(|n| n.addr == *addr).cloned()
+            };
+
+            let unknown = current
+                .iter()
+                .filter(|addr| at(addr).is_none())
+                .copied()
+                .collect::<Vec<_>>();
+            let gone = known
+                .difference(&current)
+                .filter_map(at)
+                .filter(|n| matches!(n.status, NodeStatus::Online))
+                .collect::<Vec<_>>();
+            (unknown, gone)
+        };
+        *known = current;
+
+        for addr in unknown {
+            info!("discovered {}", addr);
+            let msg = GossipMessage::update(
+                self.next_id(),
+                &[self.local_node()],
+                Some(self.config.message_ttl),
+            );
+
+            if let Err(e) = self.send(&msg, addr).await {
+                error!("Error contacting {}: {}", addr, e);
+            }
+        }
+
+        for node in gone {
+            info!("{} is no longer discovered", node.id);
+            if !self.probe(&node).await {
+                self.suspect(node.id).await;
+            }
+        }
+    }
+
+    /// Pick a random peer to probe. Nodes already declared offline or
+    /// that have left are only recovered by their own refutation.
+    async fn probe_target(&self) -> Option<Node> {
+        let mut rng = self.rng.lock().await;
+        let nodes = self.nodes.read().await;
+        let candidates = nodes
+            .values()
+            .filter(|n| n.id != self.local_id)
+            .filter(|n| {
+                matches!(n.status, NodeStatus::Online | NodeStatus::Suspect)
+            })
+            .collect::<Vec<_>>();
+
+        if candidates.is_empty() {
+            return None;
+        }
+
+        let i = sample(&mut rng, candidates.len(), 1).index(0);
+        Some(candidates[i].clone())
+    }
+
+    /// Ping `target` directly, falling back to indirect probes.
+    /// Returns true if an ack arrived by either path.
+    async fn probe(&self, target: &Node) -> bool {
+        let seq = self.probe_seq.fetch_add(1, Ordering::SeqCst);
+        let (tx, mut rx) = oneshot::channel();
+        self.pending_acks
+            .lock()
+            .await
+            .insert(seq, (PendingAck::Probe(tx), Instant::now()));
+
+        let ping = GossipMessage::ping(self.next_id(), seq);
+        if let Err(e) = self.send(&ping, target.addr).await {
+            debug!("Error pinging {}: {}", target.id, e);
+        }
+
+        if timeout(self.config.probe_timeout, &mut rx).await.is_ok() {
+            return true;
+        }
+
+        debug!("no ack from {}, probing indirectly", target.id);
+        let ping_req =
+            GossipMessage::ping_req(self.next_id(), seq, target.addr);
+        for relay in self.relay_addresses(target.id).await {
+            if let Err(e) = self.send(&ping_req, relay).await {
+                debug!("Error sending ping-req to {}: {}", relay, e);
+            }
+        }
+
+        let acked = timeout(self.config.probe_timeout * 2, &mut rx)
+            .await
+            .is_ok();
+        self.pending_acks.lock().await.remove(&seq);
+        acked
+    }
+
+    /// Up to `indirect_probes` live peers, other than `target`,
+    /// to relay a probe through.
+    async fn relay_addresses(&self, target: NodeId) -> Vec<SocketAddr> {
+        let mut rng = self.rng.lock().await;
+        let nodes = self.nodes.read().await;
+        let relays = nodes
+            .values()
+            .filter(|n| n.id != self.local_id && n.id != target)
+            .filter(|n| matches!(n.status, NodeStatus::Online))
+            .collect::<Vec<_>>();
+
+        let k = relays.len().min(self.config.indirect_probes);
+        sample(&mut rng, relays.len(), k)
+            .iter()
+            .map(|i| relays[i].addr)
+            .collect()
+    }
+
+    /// Mark a node we failed to probe as `Suspect` and tell the cluster
+    /// straight away so the node gets the chance to refute it.
+    async fn suspect(&self, node_id: NodeId) {
+        let record = {
+            let mut nodes = self.nodes.write().await;
+            let Some(node) = nodes.get_mut(&node_id) else {
+                return;
+            };
+
+            if !matches!(node.status, NodeStatus::Online) {
+                return;
+            }
+
+            info!("suspecting node {}", node_id);
+            node.status = NodeStatus::Suspect;
+            self.emit(MembershipEvent::Suspected(node.clone()));
+            node.clone()
+        };
 
-            interval.tick().await;
+        self.suspects.lock().await.insert(node_id, Instant::now());
+
+        let msg = GossipMessage::update(
+            self.next_id(),
+            &[record],
+            Some(self.config.message_ttl),
+        );
+        if let Err(e) = self.gossip(msg, Some(node_id)).await {
+            error!("Error sending suspicion: {}", e);
         }
     }
 
+    /// Declare nodes that stayed suspect past `suspicion_timeout` offline.
+    async fn expire_suspects(&self) {
+        let mut suspects = self.suspects.lock().await;
+        let mut nodes = self.nodes.write().await;
+
+        suspects.retain(|id, since| {
+            let Some(node) = nodes.get_mut(id) else {
+                return false;
+            };
+
+            if !matches!(node.status, NodeStatus::Suspect) {
+                return false;
+            }
+
+            if since.elapsed() < self.config.suspicion_timeout {
+                return true;
+            }
+
+            info!("node {} is offline", id);
+            node.status = NodeStatus::Offline;
+            self.emit(MembershipEvent::Offline(node.clone()));
+            false
+        });
+    }
+
+    /// Drop relay entries whose ack never arrived.
+    async fn expire_pending_acks(&self) {
+        let ttl = self.config.probe_timeout * 3;
+        self.pending_acks
+            .lock()
+            .await
+            .retain(|_, (_, sent)| sent.elapsed() < ttl);
+    }
+
+    /// Build the `update` messages carrying our membership digest,
+    /// including a freshly stamped record for the local node.
+    /// Records are packed into as few datagrams as they fit.
+    async fn update_messages(&self) -> Vec<GossipMessage> {
+        let mut records = vec![self.local_node()];
+        records.extend(self.nodes.read().await.values().cloned());
+
+        batches(records, self.payload_budget())
+            .iter()
+            .map(|batch| {
+                GossipMessage::update(
+                    self.next_id(),
+                    batch,
+                    Some(self.config.message_ttl),
+                )
+            })
+            .collect()
+    }
+
+    /// Build the `kv` messages carrying every replicated entry,
+    /// tombstones included, after expiring old tombstones. They are
+    /// exchanged with peers directly rather than forwarded.
+    async fn kv_messages(&self) -> Vec<GossipMessage> {
+        let entries = {
+            let mut kv = self.kv.lock().await;
+            kv.expire(self.config.tombstone_ttl);
+            kv.entries()
+                .map(|(k, e)| (k.clone(), e.clone()))
+                .collect::<Vec<_>>()
+        };
+
+        batches(entries, self.payload_budget())
+            .iter()
+            .map(|batch| GossipMessage::kv(self.next_id(), batch, 1))
+            .collect()
+    }
+
     /// Start the receive loop.
     /// This will receive messages from the network and handle them,
     /// forwarding non-system messages to the user's handler.
-    pub async fn start_receive(&self) {
+    pub async fn start_receive(self: Arc<Self>) {
         info!("starting receive");
-        let mut buf = vec![0; MAX_PAYLOAD_SIZE];
+        let mut buf = vec![0; self.config.max_datagram_size];
+        let mut shutdown = self.shutdown.subscribe();
 
         loop {
-            match self.transport.recv_from(&mut buf).await {
+            let received = tokio::select! {
+                biased;
+                _ = shutdown.wait_for(|stop| *stop) => break,
+                received = self.transport.recv_from(&mut buf) => received,
+            };
+
+            match received {
                 Ok((amt, src)) => {
                     info!("received packet from {}", src);
 
-                    if amt > MAX_PAYLOAD_SIZE {
+                    if amt > self.config.max_datagram_size {
                         error!("Received packet exceeds MTU: {} bytes", amt);
                         continue;
                     }
@@ -80,7 +1129,13 @@ impl GossipProtocol {
                         continue;
                     }
 
-                    match GossipMessage::deserialize(&buf[..amt]) {
+                    let Some(data) = self.open(&buf[..amt]) else {
+                        debug!("dropping unauthenticated packet from {}", src);
+                        self.unauthenticated.fetch_add(1, Ordering::Relaxed);
+                        continue;
+                    };
+
+                    match GossipMessage::deserialize(&data) {
                         Ok(msg) => {
                             self.handle_message(msg, src).await;
                         }
@@ -100,57 +1155,506 @@ impl GossipProtocol {
     }
 
     async fn handle_message(
-        &self,
+        self: &Arc<Self>,
         msg: GossipMessage,
         src: std::net::SocketAddr,
     ) {
         info!("Received message from {}", src);
 
+        // our own message forwarded back to us
+        if msg.from_id == self.local_id {
+            return;
+        }
+
+        // only the origin can sign a message, and only with the key
+        // we first saw it use
+        let Some(public_key) = self.authenticate(&msg).await else {
+            debug!("dropping unverified message from {}", msg.from_id);
+            self.unauthenticated.fetch_add(1, Ordering::Relaxed);
+            return;
+        };
+
+        // a copy we already handled and forwarded
+        if !self.seen.lock().await.insert(msg.id()) {
+            debug!("duplicate message {:?}", msg.id());
+            return;
+        }
+
         // update the nodes list if needed
-        self.update_nodes(msg.from_id, src).await;
+        self.update_nodes(msg.from_id, src, public_key).await;
 
-        match msg.msg_type.as_str() {
-            "heartbeat" => {
-                self.update_heartbeat(msg.from_id).await;
+        match msg.kind {
+            MessageKind::Heartbeat => match msg.incarnation() {
+                Ok(incarnation) => {
+                    self.update_heartbeat(msg.from_id, incarnation).await
+                }
+                Err(e) => {
+                    error!("Invalid heartbeat from {}: {}", msg.from_id, e)
+                }
+            },
+            MessageKind::Ping => match msg.probe_seq() {
+                Ok(seq) => self.handle_ping(msg.from_id, seq, src).await,
+                Err(e) => error!("Invalid ping from {}: {}", msg.from_id, e),
+            },
+            MessageKind::PingReq => match msg.ping_request() {
+                Ok(req) => self.handle_ping_req(req.seq, req.target, src).await,
+                Err(e) => {
+                    error!("Invalid ping-req from {}: {}", msg.from_id, e)
+                }
+            },
+            MessageKind::Ack => match msg.probe_seq() {
+                Ok(seq) => self.handle_ack(msg.from_id, seq).await,
+                Err(e) => error!("Invalid ack from {}: {}", msg.from_id, e),
+            },
+            MessageKind::Update => match msg.nodes() {
+                Ok(records) => self.merge_nodes(msg.from_id, records).await,
+                Err(e) => error!("Invalid update from {}: {}", msg.from_id, e),
+            },
+            MessageKind::Leave => match msg.incarnation() {
+                Ok(incarnation) => {
+                    self.mark_left(msg.from_id, incarnation).await
+                }
+                Err(e) => error!("Invalid leave from {}: {}", msg.from_id, e),
+            },
+            // relayed as a whole once complete, not one by one
+            MessageKind::Fragment => {
+                match msg.fragment() {
+                    Ok(fragment) => self.reassemble(msg, fragment).await,
+                    Err(e) => {
+                        error!("Invalid fragment from {}: {}", msg.from_id, e)
+                    }
+                }
+                return;
             }
-            _ => {
-                error!("Unknown message type: {}", msg.msg_type);
+            MessageKind::Kv => match msg.entries() {
+                Ok(entries) => self.merge_entries(entries).await,
+                Err(e) => error!("Invalid entries from {}: {}", msg.from_id, e),
+            },
+            MessageKind::Sync => {
+                debug!("ignoring sync from {}", msg.from_id);
+            }
+            MessageKind::Application(_)
+            | MessageKind::Request(_)
+            | MessageKind::Response(_)
+            | MessageKind::Publish => {
+                self.deliver(&msg).await;
             }
         }
 
         self.forward(msg).await;
     }
 
+    /// Hand a message meant for the application to its handler, its
+    /// responder, the request waiting on it or its topic's subscribers.
+    async fn deliver(self: &Arc<Self>, msg: &GossipMessage) {
+        match msg.kind {
+            MessageKind::Application(msg_type) => {
+                self.dispatch(msg_type, msg).await
+            }
+            MessageKind::Request(msg_type) => self.respond(msg_type, msg).await,
+            MessageKind::Response(seq) => self.complete_request(seq, msg).await,
+            MessageKind::Publish => match msg.publication() {
+                Ok(publication) => {
+                    self.receive_publication(msg.from_id, publication).await
+                }
+                Err(e) => {
+                    error!("Invalid publication from {}: {}", msg.from_id, e)
+                }
+            },
+            _ => {}
+        }
+    }
+
+    /// Hand a publication to our subscribers of its topic. Nodes that
+    /// don't subscribe only relay it.
+    async fn receive_publication(
+        &self,
+        from_id: NodeId,
+        publication: Publication,
+    ) {
+        let msg = TopicMessage {
+            from_id,
+            topic: publication.topic,
+            payload: publication.data,
+        };
+
+        if !self.subscriptions.lock().await.deliver(msg) {
+            debug!("relaying publication from {}", from_id);
+        }
+    }
+
+    /// Run the responder registered for a request on its own task and
+    /// send its reply back to the requester.
+    async fn respond(self: &Arc<Self>, msg_type: u16, msg: &GossipMessage) {
+        let Some(responder) = self.handlers.responder(msg_type) else {
+            error!("No responder for request type: {}", msg_type);
+            return;
+        };
+
+        let Some(from) = self.nodes.read().await.get(&msg.from_id).cloned()
+        else {
+            debug!("request from unknown node {}", msg.from_id);
+            return;
+        };
+
+        let p = Arc::clone(self);
+        let msg = msg.clone();
+        tokio::spawn(async move {
+            let addr = from.addr;
+            let reply = match responder.respond(from, msg.clone()).await {
+                Ok(reply) => reply,
+                Err(e) => {
+                    error!("Error encoding reply to {}: {}", msg.from_id, e);
+                    return;
+                }
+            };
+            let response = GossipMessage::response(p.next_id(), msg.seq, reply);
+
+            if let Err(e) = p.unicast(&response, addr).await {
+                error!("Error responding to {}: {}", msg.from_id, e);
+            }
+        });
+    }
+
+    /// Hand a response to the request waiting on it, if it came from
+    /// the node the request was sent to.
+    async fn complete_request(&self, seq: u64, msg: &GossipMessage) {
+        let mut pending = self.pending_requests.lock().await;
+
+        match pending.get(&seq) {
+            Some((node_id, _)) if *node_id == msg.from_id => {
+                if let Some((_, tx)) = pending.remove(&seq) {
+                    let _ = tx.send(msg.clone());
+                }
+            }
+            Some(_) => {
+                debug!(
+                    "response to {} from the wrong node {}",
+                    seq, msg.from_id
+                )
+            }
+            None => debug!("late response {} from {}", seq, msg.from_id),
+        }
+    }
+
+    /// Hand an application message to subscribers and its registered
+    /// handler. The handler runs on its own task so a slow handler
+    /// doesn't stall the receive loop.
+    async fn dispatch(&self, msg_type: u16, msg: &GossipMessage) {
+        let subscribed = self.messages.send(msg.clone()).is_ok();
+
+        let Some(handler) = self.handlers.get(msg_type) else {
+            if !subscribed {
+                error!("Unknown message type: {}", msg_type);
+            }
+            return;
+        };
+
+        let Some(from) = self.nodes.read().await.get(&msg.from_id).cloned()
+        else {
+            debug!("message from unknown node {}", msg.from_id);
+            return;
+        };
+
+        let msg = msg.clone();
+        tokio::spawn(async move {
+            handler.handle(from, msg).await;
+        });
+    }
+
+    /// Buffer a fragment. Once it completes a message, dispatch the
+    /// message and relay all of its fragments to the same peers, so
+    /// nodes past the first hop receive every chunk.
+    async fn reassemble(&self, msg: GossipMessage, fragment: Fragment) {
+        let Some(complete) = self.fragments.lock().await.insert(msg, fragment)
+        else {
+            return;
+        };
+
+        let msg = GossipMessage::reassembled(
+            complete.id,
+            complete.msg_type,
+            complete.payload,
+        );
+        self.dispatch(complete.msg_type, &msg).await;
+        self.forward_fragments(complete.id.origin, complete.fragments)
+            .await;
+    }
+
+    /// Relay the fragments of a message, all to one set of peers.
+    async fn forward_fragments(
+        &self,
+        origin: NodeId,
+        mut fragments: Vec<GossipMessage>,
+    ) {
+        for fragment in &mut fragments {
+            fragment.ttl = fragment.ttl.saturating_sub(1);
+        }
+        if fragments.iter().any(|f| f.ttl == 0) {
+            return;
+        }
+
+        let addresses = self.gossip_addresses(Some(origin), None).await;
+        for fragment in &fragments {
+            for addr in &addresses {
+                if let Err(e) = self.send(fragment, *addr).await {
+                    debug!("Error relaying fragment to {}: {}", addr, e);
+                }
+            }
+        }
+    }
+
+    async fn handle_ping(&self, from_id: NodeId, seq: u32, src: SocketAddr) {
+        let ack = GossipMessage::ack(self.next_id(), seq);
+        if let Err(e) = self.send(&ack, src).await {
+            debug!("Error acking ping from {}: {}", from_id, e);
+        }
+    }
+
+    /// Ping `target` on behalf of `requester`, relaying the ack back.
+    async fn handle_ping_req(
+        &self,
+        seq: u32,
+        target: SocketAddr,
+        requester: SocketAddr,
+    ) {
+        let relay_seq = self.probe_seq.fetch_add(1, Ordering::SeqCst);
+        self.pending_acks.lock().await.insert(
+            relay_seq,
+            (PendingAck::Relay { requester, seq }, Instant::now()),
+        );
+
+        let ping = GossipMessage::ping(self.next_id(), relay_seq);
+        if let Err(e) = self.send(&ping, target).await {
+            debug!("Error relaying ping to {}: {}", target, e);
+        }
+    }
+
+    async fn handle_ack(&self, from_id: NodeId, seq: u32) {
+        let pending = self.pending_acks.lock().await.remove(&seq);
+
+        match pending {
+            Some((PendingAck::Probe(tx), _)) => {
+                let _ = tx.send(());
+            }
+            Some((PendingAck::Relay { requester, seq }, _)) => {
+                let ack = GossipMessage::ack(self.next_id(), seq);
+                if let Err(e) = self.send(&ack, requester).await {
+                    debug!("Error relaying ack to {}: {}", requester, e);
+                }
+            }
+            None => debug!("late ack {} from {}", seq, from_id),
+        }
+    }
+
     async fn forward(&self, mut msg: GossipMessage) {
         let exclude_id = Some(msg.from_id);
-        msg.ttl -= 1;
+        msg.ttl = msg.ttl.saturating_sub(1);
 
         if msg.ttl > 0 {
             let _ = self.gossip(msg, exclude_id).await;
         }
     }
 
-    async fn update_nodes(&self, node_id: u32, src: std::net::SocketAddr) {
+    /// The key a message was signed with, if it is signed by the key
+    /// its origin is pinned to.
+    async fn authenticate(&self, msg: &GossipMessage) -> Option<VerifyingKey> {
+        let public_key = msg.verify()?;
+
+        let nodes = self.nodes.read().await;
+        match nodes.get(&msg.from_id).and_then(Node::pinned_key) {
+            Some(pinned) if pinned != public_key => None,
+            _ => Some(public_key),
+        }
+    }
+
+    async fn update_nodes(
+        &self,
+        node_id: NodeId,
+        src: std::net::SocketAddr,
+        public_key: VerifyingKey,
+    ) {
         let mut nodes = self.nodes.write().await;
 
-        // if the node is not in the peers list, add it
-        if !nodes.keys().any(|n| *n == node_id) {
-            info!("new node {}", node_id);
-            nodes.insert(node_id, Node::new(node_id, src));
+        match nodes.get_mut(&node_id) {
+            // if the node is not in the peers list, add it
+            None => {
+                info!("new node {}", node_id);
+                let mut node = Node::new(node_id, src);
+                node.public_key = Some(public_key);
+                self.emit(MembershipEvent::Joined(node.clone()));
+                nodes.insert(node_id, node);
+            }
+            // pin the key of a seed or of a node back with a new identity
+            Some(node) if node.public_key != Some(public_key) => {
+                node.public_key = Some(public_key);
+                node.signature = None;
+            }
+            Some(_) => {}
         }
     }
 
-    async fn update_heartbeat(&self, node_id: u32) {
+    /// Merge node records received from a peer into the local table.
+    /// Unknown nodes are added; known nodes take the record if it
+    /// supersedes ours (see `Node::merge`). Records claiming we are
+    /// not alive are refuted with a higher incarnation. Records not
+    /// signed by the node they describe, or signed with a key other
+    /// than the one it is pinned to, are dropped.
+    async fn merge_nodes(&self, from_id: NodeId, records: Vec<Node>) {
+        let mut refute = false;
+        let mut suspected = Vec::new();
+
+        {
+            let mut nodes = self.nodes.write().await;
+
+            for mut record in records {
+                if record.id == self.local_id {
+                    refute |= self.needs_refutation(&record);
+                    continue;
+                }
+
+                if !record.verify() {
+                    debug!("dropping unsigned record for {}", record.id);
+                    continue;
+                }
+
+                // only a node itself can announce its departure
+                if record.has_left() && record.id != from_id {
+                    continue;
+                }
+
+                match nodes.get_mut(&record.id) {
+                    Some(node) => {
+                        if node
+                            .pinned_key()
+                            .is_some_and(|key| record.public_key != Some(key))
+                        {
+                            debug!(
+                                "dropping record for {} under another key",
+                                node.id
+                            );
+                            continue;
+                        }
+
+                        let previous = node.status.clone();
+                        let newer_metadata =
+                            record.meta_version > node.meta_version;
+
+                        // newer metadata, and the address of a node learned
+                        // of through a forwarded message, are taken from
+                        // records the node signed
+                        let adopted = record.incarnation == node.incarnation
+                            && (newer_metadata
+                                || record.meta_version == node.meta_version
+                                    && (record.id == from_id
+                                        || !node.verify()));
+                        if adopted {
+                            node.adopt(&record);
+                        }
+
+                        if !node.merge(&record) {
+                            if adopted && newer_metadata {
+                                self.emit(MembershipEvent::Updated(
+                                    node.clone(),
+                                ));
+                            }
+                            continue;
+                        }
+
+                        if matches!(node.status, NodeStatus::Suspect) {
+                            suspected.push(node.id);
+                        }
+                        self.emit(MembershipEvent::transition(&previous, node));
+                    }
+                    None => {
+                        info!("new node {} via update", record.id);
+                        record.last_heartbeat = SystemTime::now();
+                        if matches!(record.status, NodeStatus::Suspect) {
+                            suspected.push(record.id);
+                        }
+                        self.emit(MembershipEvent::Joined(record.clone()));
+                        nodes.insert(record.id, record);
+                    }
+                }
+            }
+        }
+
+        let mut suspects = self.suspects.lock().await;
+        for id in suspected {
+            suspects.entry(id).or_insert_with(Instant::now);
+        }
+        drop(suspects);
+
+        if refute {
+            self.refute().await;
+        }
+    }
+
+    /// A peer's record of us needs refuting if it isn't `Online` at
+    /// an incarnation at least as new as ours, unless we're leaving.
+    fn needs_refutation(&self, record: &Node) -> bool {
+        !self.leaving.load(Ordering::SeqCst)
+            && !matches!(record.status, NodeStatus::Online)
+            && record.incarnation >= self.incarnation.load(Ordering::SeqCst)
+    }
+
+    /// Bump our incarnation above the suspicion and announce we're alive.
+    async fn refute(&self) {
+        let incarnation = self.incarnation.fetch_add(1, Ordering::SeqCst) + 1;
+        info!("refuting suspicion with incarnation {}", incarnation);
+
+        if let Err(e) = self.announce().await {
+            error!("Error sending refutation: {}", e);
+        }
+    }
+
+    /// Gossip our own record ahead of the next round.
+    async fn announce(&self) -> Result<(), GossipError> {
+        let msg = GossipMessage::update(
+            self.next_id(),
+            &[self.local_node()],
+            Some(self.config.message_ttl),
+        );
+
+        self.gossip(msg, None).await
+    }
+
+    /// Mark a node as having left at `incarnation` or its current one,
+    /// whichever is newer.
+    async fn mark_left(&self, node_id: NodeId, incarnation: u64) {
         let mut nodes = self.nodes.write().await;
-        error!("nodes: {}", nodes.len());
         if let Some(node) = nodes.get_mut(&node_id) {
-            node.update_heartbeat();
+            info!("node {} left", node_id);
+            node.incarnation = node.incarnation.max(incarnation);
+            node.leave();
+            self.emit(MembershipEvent::Left(node.clone()));
         }
     }
 
+    /// Record a heartbeat. It only revives a suspect or offline node if
+    /// it carries a newer incarnation, i.e. the node refuted the claim.
+    async fn update_heartbeat(&self, node_id: NodeId, incarnation: u64) {
+        let mut nodes = self.nodes.write().await;
+        if let Some(node) = nodes.get_mut(&node_id) {
+            node.last_heartbeat = SystemTime::now();
+
+            if incarnation > node.incarnation {
+                let previous = node.status.clone();
+                node.incarnation = incarnation;
+                node.update_heartbeat();
+                self.emit(MembershipEvent::transition(&previous, node));
+            }
+        }
+    }
+
+    /// Up to `fanout` live peers to gossip to, plus an offline one to
+    /// rescue. Subscribers of `topic` are picked first; the rest are
+    /// filled from other peers, which relay to subscribers we don't
+    /// know of.
     async fn gossip_addresses(
         &self,
-        exclude_id: Option<u32>,
+        exclude_id: Option<NodeId>,
+        topic: Option<&str>,
     ) -> Vec<SocketAddr> {
         let mut rng = self.rng.lock().await;
         let peers = self.nodes.read().await;
@@ -158,20 +1662,30 @@ impl GossipProtocol {
         let valid_peers = peers
             .values()
             .filter(|n| !n.is_offline(self.config.offline_timeout))
-            .filter(|n| n.id != self.local_node.id)
+            .filter(|n| n.id != self.local_id)
             .filter(|n| exclude_id.map(|id| n.id != id).unwrap_or(true))
             .collect::<Vec<_>>();
 
         let fanout = valid_peers.len().min(self.config.fanout);
+        let (subscribed, others): (Vec<_>, Vec<_>) = valid_peers
+            .into_iter()
+            .partition(|n| topic.is_some_and(|t| n.subscribes_to(t)));
 
-        let mut addresses = sample(&mut rng, valid_peers.len(), fanout)
+        let preferred = subscribed.len().min(fanout);
+        let mut addresses = sample(&mut rng, subscribed.len(), preferred)
             .iter()
-            .map(|i| valid_peers[i].addr)
+            .map(|i| subscribed[i].addr)
             .collect::<Vec<_>>();
+        addresses.extend(
+            sample(&mut rng, others.len(), fanout - preferred)
+                .iter()
+                .map(|i| others[i].addr),
+        );
 
         let offline_peers = peers
             .values()
             .filter(|n| n.is_offline(self.config.offline_timeout))
+            .filter(|n| !n.has_left())
             .filter(|n| exclude_id.map(|id| n.id != id).unwrap_or(true))
             .collect::<Vec<_>>();
 
@@ -193,29 +1707,184 @@ impl GossipProtocol {
         addresses
     }
 
+    /// Send a message to a single address.
+    async fn send(
+        &self,
+        msg: &GossipMessage,
+        addr: SocketAddr,
+    ) -> Result<(), GossipError> {
+        let buf = self.encode(msg)?;
+        self.transport.write(&buf, addr.to_string()).await?;
+        Ok(())
+    }
+
+    /// Sign, serialize and seal a message, refusing any too large for
+    /// a datagram. Forwarded messages keep their origin's signature.
+    fn encode(&self, msg: &GossipMessage) -> Result<Vec<u8>, GossipError> {
+        let buf = self.seal(msg)?;
+        if buf.len() > self.config.max_datagram_size {
+            return Err(GossipError::PayloadTooLarge(buf.len()));
+        }
+
+        Ok(buf)
+    }
+
+    /// Sign, serialize and seal a message of any size.
+    fn seal(&self, msg: &GossipMessage) -> Result<Vec<u8>, GossipError> {
+        let mut buf = if msg.from_id == self.local_id {
+            let mut msg = msg.clone();
+            msg.sign(&self.signing_key);
+            GossipMessage::serialize(&msg)?
+        } else {
+            GossipMessage::serialize(msg)?
+        };
+        if let Some(keyring) = self.keyring.read().unwrap().as_ref() {
+            buf = keyring.seal(&buf)?;
+        }
+
+        Ok(buf)
+    }
+
+    /// Authenticate and decrypt a received datagram. Without a keyring
+    /// packets are passed through as-is.
+    fn open<'a>(&self, packet: &'a [u8]) -> Option<Cow<'a, [u8]>> {
+        match self.keyring.read().unwrap().as_ref() {
+            Some(keyring) => keyring.open(packet).map(Cow::Owned),
+            None => Some(Cow::Borrowed(packet)),
+        }
+    }
+
     pub async fn gossip(
         &self,
         msg: GossipMessage,
-        exclude_id: Option<u32>,
+        exclude_id: Option<NodeId>,
     ) -> Result<(), GossipError> {
-        let addresses = self.gossip_addresses(exclude_id).await;
+        let topic = match msg.kind {
+            MessageKind::Publish => Some(msg.publication()?.topic),
+            _ => None,
+        };
+        let addresses =
+            self.gossip_addresses(exclude_id, topic.as_deref()).await;
         info!("gossiping to {:?}", addresses);
 
+        let buf = self.seal(&msg)?;
+        if buf.len() > self.config.max_datagram_size {
+            if !self.transport.supports_streams() {
+                return Err(GossipError::PayloadTooLarge(buf.len()));
+            }
+
+            for addr in addresses {
+                if let Err(e) = self.send_stream(&msg, &buf, addr).await {
+                    error!("Error streaming message to {}: {}", addr, e);
+                }
+            }
+            return Ok(());
+        }
+
         for addr in addresses {
-            let buf = GossipMessage::serialize(&msg)?;
             self.transport.write(&buf, addr.to_string()).await?;
             sleep(Duration::from_millis(1)).await;
         }
 
         Ok(())
     }
+
+    /// Send a message to a single node: in a datagram if it fits,
+    /// otherwise over a stream, or as fragments if the transport has
+    /// no streams. Only application messages can be fragmented.
+    async fn unicast(
+        &self,
+        msg: &GossipMessage,
+        addr: SocketAddr,
+    ) -> Result<(), GossipError> {
+        if msg.payload.len() > self.config.max_message_size {
+            return Err(GossipError::PayloadTooLarge(msg.payload.len()));
+        }
+
+        let buf = self.seal(msg)?;
+        if buf.len() <= self.config.max_datagram_size {
+            self.transport.write(&buf, addr.to_string()).await?;
+            return Ok(());
+        }
+
+        if self.transport.supports_streams() {
+            return self.send_stream(msg, &buf, addr).await;
+        }
+
+        for fragment in
+            msg.fragments(self.payload_budget(), || self.next_id())?
+        {
+            self.send(&fragment, addr).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Send a sealed message too large for a datagram over a stream.
+    /// It follows an update with our record and that of the message's
+    /// origin, so the receiver knows both by their gossip addresses.
+    async fn send_stream(
+        &self,
+        msg: &GossipMessage,
+        buf: &[u8],
+        addr: SocketAddr,
+    ) -> Result<(), GossipError> {
+        let mut records = vec![self.local_node()];
+        if let Some(origin) = self.nodes.read().await.get(&msg.from_id) {
+            records.push(origin.clone());
+        }
+        let intro = GossipMessage::update(self.next_id(), &records, Some(1));
+        let intro = self.seal(&intro)?;
+
+        let mut stream = self.transport.connect_stream(addr).await?;
+        timeout(STREAM_TIMEOUT, async {
+            write_frame(stream.as_mut(), &intro).await?;
+            write_frame(stream.as_mut(), buf).await
+        })
+        .await
+        .map_err(|_| {
+            GossipError::NetworkError(format!("stream to {} timed out", addr))
+        })?
+    }
+}
+
+/// Split `items` into batches whose encodings fit `budget` bytes.
+/// An item larger than the budget gets a batch of its own.
+fn batches<T: Serialize>(items: Vec<T>, budget: usize) -> Vec<Vec<T>> {
+    let mut batches = vec![];
+    let mut batch = vec![];
+    let mut size = 0;
+
+    for item in items {
+        let len = match postcard::to_allocvec(&item) {
+            Ok(buf) => buf.len(),
+            Err(e) => {
+                error!("Error encoding batch item: {}", e);
+                continue;
+            }
+        };
+
+        if size + len > budget && !batch.is_empty() {
+            batches.push(std::mem::take(&mut batch));
+            size = 0;
+        }
+
+        size += len;
+        batch.push(item);
+    }
+
+    if !batch.is_empty() {
+        batches.push(batch);
+    }
+
+    batches
 }
 
 #[async_trait]
 pub trait GossipTransport: Send + Sync {
     async fn write(
         &self,
-        buf: &heapless::Vec<u8, MAX_PAYLOAD_SIZE>,
+        buf: &[u8],
         addr: String,
     ) -> Result<usize, GossipError>;
 
@@ -225,4 +1894,32 @@ pub trait GossipTransport: Send + Sync {
     ) -> Result<(usize, SocketAddr), GossipError>;
 
     async fn get_ip(&self) -> Result<String, GossipError>;
+
+    /// Whether the transport provides streams. Transports without
+    /// them keep the defaults, and features that need one, like
+    /// push-pull sync, are skipped; large messages are fragmented.
+    fn supports_streams(&self) -> bool {
+        false
+    }
+
+    /// Open a reliable stream to the node listening at `addr`.
+    async fn connect_stream(
+        &self,
+        _addr: SocketAddr,
+    ) -> Result<Box<dyn GossipStream>, GossipError> {
+        Err(GossipError::Unsupported("streams".to_string()))
+    }
+
+    /// Wait for a peer to open a stream to us. The address is only
+    /// logged; it may be an ephemeral port the peer doesn't gossip on.
+    async fn accept_stream(
+        &self,
+    ) -> Result<(Box<dyn GossipStream>, SocketAddr), GossipError> {
+        Err(GossipError::Unsupported("streams".to_string()))
+    }
+
+    /// Release the transport's listener. Called once on shutdown.
+    async fn close(&self) -> Result<(), GossipError> {
+        Ok(())
+    }
 }
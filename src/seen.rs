@@ -0,0 +1,48 @@
+use std::collections::{HashSet, VecDeque};
+use std::time::{Duration, Instant};
+
+use crate::message::MessageId;
+
+/// Bounded record of recently seen message IDs.
+/// Entries are forgotten once older than `window` or when more than
+/// `capacity` messages arrived after them, whichever comes first.
+pub struct SeenCache {
+    capacity: usize,
+    window: Duration,
+    order: VecDeque<(MessageId, Instant)>,
+    seen: HashSet<MessageId>,
+}
+
+impl SeenCache {
+    pub fn new(capacity: usize, window: Duration) -> Self {
+        SeenCache {
+            capacity,
+            window,
+            order: VecDeque::with_capacity(capacity),
+            seen: HashSet::with_capacity(capacity),
+        }
+    }
+
+    /// Record `id`; returns false if it was already seen.
+    pub fn insert(&mut self, id: MessageId) -> bool {
+        self.evict();
+
+        if !self.seen.insert(id) {
+            return false;
+        }
+
+        self.order.push_back((id, Instant::now()));
+        true
+    }
+
+    fn evict(&mut self) {
+        while let Some((id, at)) = self.order.front() {
+            if self.order.len() < self.capacity && at.elapsed() < self.window {
+                break;
+            }
+
+            self.seen.remove(id);
+            self.order.pop_front();
+        }
+    }
+}
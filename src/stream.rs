@@ -0,0 +1,42 @@
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::error::GossipError;
+
+/// Reliable, ordered byte stream between two nodes, opened with
+/// `GossipTransport::connect_stream`.
+pub trait GossipStream: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> GossipStream for T {}
+
+/// Write `data` prefixed with its length as a big-endian `u32`.
+pub async fn write_frame(
+    stream: &mut dyn GossipStream,
+    data: &[u8],
+) -> Result<(), GossipError> {
+    let len = u32::try_from(data.len())
+        .map_err(|_| GossipError::PayloadTooLarge(data.len()))?;
+
+    stream.write_all(&len.to_be_bytes()).await?;
+    stream.write_all(data).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+/// Read a frame written by `write_frame`, refusing any larger than
+/// `max_size` before reading its body.
+pub async fn read_frame(
+    stream: &mut dyn GossipStream,
+    max_size: usize,
+) -> Result<Vec<u8>, GossipError> {
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len).await?;
+
+    let len = u32::from_be_bytes(len) as usize;
+    if len > max_size {
+        return Err(GossipError::PayloadTooLarge(len));
+    }
+
+    let mut data = vec![0; len];
+    stream.read_exact(&mut data).await?;
+    Ok(data)
+}
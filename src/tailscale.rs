@@ -1,26 +1,211 @@
 use crate::GossipConfig;
-use crate::constants::MAX_PAYLOAD_SIZE;
+use crate::discovery::Discovery;
 use crate::error::GossipError;
 use crate::node::Node;
 use crate::protocol::GossipTransport;
+use crate::stream::GossipStream;
 
 use crate::util::{extract_ipv4, hash_node_name, make_id};
 use async_trait::async_trait;
 
-use log::info;
-use std::net::{SocketAddr, UdpSocket};
+use log::{debug, error, info};
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
 
-use std::os::fd::AsFd;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use std::os::fd::{AsFd, OwnedFd};
+use std::os::unix::net::UnixStream;
 use std::path::PathBuf;
-use tailscale_api::Tailscale as TailscaleApi;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::sync::{Mutex as AsyncMutex, mpsc};
 use tsnet::{ConfigBuilder, TSNet, TailscaleListener};
 
+/// Dialed sockets unused for this long are closed.
+const CONN_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+/// How often blocked readers wake to check for shutdown and idleness.
+const READ_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Tag a device carries to advertise a gossip port other than
+/// the configured one, e.g. `tag:gossip-port-7946`.
+const PORT_TAG_PREFIX: &str = "tag:gossip-port-";
+
+const API_ENDPOINT: &str = "https://api.tailscale.com/api/v2";
+
+type Packet = (Vec<u8>, SocketAddr);
+type Incoming = (OwnedFd, SocketAddr);
+
+/// A device in the tailnet, as listed by the Tailscale API.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Device {
+    #[serde(default)]
+    pub addresses: Vec<String>,
+    #[serde(default)]
+    pub hostname: String,
+    #[serde(default)]
+    pub name: String,
+    /// ACL tags, e.g. `tag:gossip`
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub last_seen: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub authorized: bool,
+    /// Whether the device is currently connected to the control plane
+    #[serde(default)]
+    pub connected_to_control: bool,
+}
+
+#[derive(Deserialize)]
+struct DeviceList {
+    #[serde(default)]
+    devices: Vec<Device>,
+}
+
+/// Which devices in the tailnet are gossip peers.
+/// A device must pass every criterion that is set.
+#[derive(Debug, Clone, Default)]
+pub struct PeerSelector {
+    /// Hostname prefix devices must start with
+    pub hostname_prefix: Option<String>,
+    /// ACL tags; devices must carry at least one when non-empty
+    pub tags: Vec<String>,
+    /// Only devices currently connected to the control plane
+    pub online_only: bool,
+    /// Only devices seen within this long
+    pub last_seen_within: Option<Duration>,
+}
+
+impl PeerSelector {
+    pub fn matches(&self, device: &Device) -> bool {
+        let prefix = self
+            .hostname_prefix
+            .as_ref()
+            .is_none_or(|p| device.hostname.starts_with(p));
+
+        let tagged = self.tags.is_empty()
+            || device.tags.iter().any(|t| self.tags.contains(t));
+
+        let online = !self.online_only || device.connected_to_control;
+
+        let recent = match (self.last_seen_within, device.last_seen) {
+            (None, _) => true,
+            (Some(_), None) => false,
+            (Some(window), Some(last_seen)) => (Utc::now() - last_seen)
+                .to_std()
+                .map_or(true, |age| age <= window),
+        };
+
+        device.authorized && prefix && tagged && online && recent
+    }
+}
+
+impl Device {
+    /// Port the device advertises with a `PORT_TAG_PREFIX` tag, if any.
+    pub fn advertised_port(&self) -> Option<u16> {
+        self.tags
+            .iter()
+            .find_map(|t| t.strip_prefix(PORT_TAG_PREFIX)?.parse().ok())
+    }
+}
+
+/// Gossip port of each device seen in the tailnet, by tailnet IP.
+/// Accepted connections come from ephemeral ports, so this is how
+/// the peer on one is labelled with the address it listens on.
+struct PeerPorts {
+    ports: Mutex<HashMap<Ipv4Addr, u16>>,
+    /// Port of devices not listed yet or without a port tag
+    default: u16,
+}
+
+impl PeerPorts {
+    fn new(default: u16) -> Self {
+        PeerPorts {
+            ports: Mutex::new(HashMap::new()),
+            default,
+        }
+    }
+
+    fn record(&self, ip: Ipv4Addr, device: &Device) -> u16 {
+        let port = device.advertised_port().unwrap_or(self.default);
+        self.ports.lock().unwrap().insert(ip, port);
+        port
+    }
+
+    fn addr(&self, ip: Ipv4Addr) -> SocketAddr {
+        let port = self.ports.lock().unwrap().get(&ip).copied();
+        SocketAddr::from((ip, port.unwrap_or(self.default)))
+    }
+}
+
+/// Minimal client for the Tailscale device API. The `tailscale-api`
+/// crate's device type lacks the tags and control-plane status
+/// `PeerSelector` filters on, and fails to parse devices that have
+/// never been seen, so the list is fetched with `fields=all` here.
+#[derive(Clone)]
+struct DeviceApi {
+    client: reqwest::Client,
+    key: String,
+    tailnet: String,
+}
+
+impl DeviceApi {
+    /// Read credentials from `TAILSCALE_API_KEY` and `TAILSCALE_DOMAIN`.
+    fn from_env() -> Result<Self, std::env::VarError> {
+        Ok(DeviceApi {
+            client: reqwest::Client::new(),
+            key: std::env::var("TAILSCALE_API_KEY")?,
+            tailnet: std::env::var("TAILSCALE_DOMAIN")?,
+        })
+    }
+
+    async fn list_devices(&self) -> Result<Vec<Device>, GossipError> {
+        let url = format!("{}/tailnet/{}/devices", API_ENDPOINT, self.tailnet);
+        let resp = self
+            .client
+            .get(url)
+            .query(&[("fields", "all")])
+            .basic_auth(&self.key, Some(""))
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| GossipError::PeerError(e.to_string()))?;
+
+        let list = resp
+            .json::<DeviceList>()
+            .await
+            .map_err(|e| GossipError::PeerError(e.to_string()))?;
+
+        Ok(list.devices)
+    }
+}
+
+struct CachedConn {
+    socket: Arc<UdpSocket>,
+    last_used: Instant,
+}
+
 pub struct Tailscale {
     pub id: String,
+    /// Which devices `get_peers` returns; by default those whose
+    /// hostname starts with the configured prefix.
+    pub selector: PeerSelector,
     gossip_config: GossipConfig,
-    ts: TSNet,
-    api: TailscaleApi,
-    listener: Option<TailscaleListener>,
+    ts: Arc<TSNet>,
+    api: DeviceApi,
+    ports: Arc<PeerPorts>,
+    /// Dialed sockets keyed by peer address, reused across writes.
+    conns: Mutex<HashMap<String, CachedConn>>,
+    /// Datagrams read by the accept loop's connection readers.
+    packets: AsyncMutex<Option<mpsc::UnboundedReceiver<Packet>>>,
+    /// TCP connections accepted by the stream accept loop.
+    streams: AsyncMutex<Option<mpsc::UnboundedReceiver<Incoming>>>,
+    listen_addr: Option<String>,
+    closed: Arc<AtomicBool>,
 }
 
 impl Tailscale {
@@ -29,7 +214,12 @@ impl Tailscale {
         state_dir: Option<PathBuf>,
     ) -> Result<Self, Box<dyn std::error::Error>> {
         let node_id = make_id(&gossip_config.prefix);
-        let api = TailscaleApi::new_from_env();
+        let api = DeviceApi::from_env()?;
+        let ports = Arc::new(PeerPorts::new(gossip_config.gossip_port));
+        let selector = PeerSelector {
+            hostname_prefix: Some(format!("{}-", gossip_config.prefix)),
+            ..PeerSelector::default()
+        };
 
         let ts_config = if let Some(state_dir) = state_dir {
             ConfigBuilder::new()
@@ -43,64 +233,125 @@ impl Tailscale {
 
         Ok(Self {
             id: node_id,
+            selector,
             gossip_config,
-            ts,
+            ts: Arc::new(ts),
             api,
-            listener: None,
+            ports,
+            conns: Mutex::new(HashMap::new()),
+            packets: AsyncMutex::new(None),
+            streams: AsyncMutex::new(None),
+            listen_addr: None,
+            closed: Arc::new(AtomicBool::new(false)),
         })
     }
 
+    /// Start listening for gossip. Accepted connections are read by
+    /// long-lived reader threads feeding `recv_from`; TCP connections
+    /// on the same port are handed to `accept_stream`.
     pub async fn listen(&mut self) -> Result<(), GossipError> {
-        if self.listener.is_none() {
+        if self.listen_addr.is_none() {
             let ip_addr = self.get_ip().await?;
             let ip_addr = extract_ipv4(&ip_addr)?;
             let ip_addr = ip_addr.to_string();
             let listen_addr =
                 format!("{}:{}", ip_addr, self.gossip_config.gossip_port);
 
-            self.listener = Some(
-                self.ts
-                    .listen("udp", &listen_addr)
-                    .map_err(|e| GossipError::NetworkError(e))?,
-            );
+            let listener = self
+                .ts
+                .listen("udp", &listen_addr)
+                .map_err(GossipError::NetworkError)?;
+
+            let (tx, rx) = mpsc::unbounded_channel();
+            let ts = Arc::clone(&self.ts);
+            let closed = Arc::clone(&self.closed);
+            let ports = Arc::clone(&self.ports);
+            let buf_size = self.gossip_config.max_datagram_size;
+            thread::spawn(move || {
+                accept_loop(ts, listener, ports, buf_size, tx, closed)
+            });
+
+            let stream_listener = self
+                .ts
+                .listen("tcp", &listen_addr)
+                .map_err(GossipError::NetworkError)?;
+
+            let (streams_tx, streams_rx) = mpsc::unbounded_channel();
+            let ts = Arc::clone(&self.ts);
+            let closed = Arc::clone(&self.closed);
+            let ports = Arc::clone(&self.ports);
+            thread::spawn(move || {
+                accept_streams(ts, stream_listener, ports, streams_tx, closed)
+            });
+
+            *self.packets.get_mut() = Some(rx);
+            *self.streams.get_mut() = Some(streams_rx);
+            self.listen_addr = Some(listen_addr);
         }
 
         Ok(())
     }
 
     pub async fn join_network(&mut self) -> Result<String, GossipError> {
-        self.ts.up().map_err(|e| GossipError::NetworkError(e))?;
+        // only shared with reader threads once `listen` is called
+        let ts = Arc::get_mut(&mut self.ts).ok_or_else(|| {
+            GossipError::NetworkError("already listening".to_string())
+        })?;
+        ts.up().map_err(GossipError::NetworkError)?;
         Ok(self.id.clone())
     }
 
     pub async fn get_ip(&self) -> Result<String, GossipError> {
-        let ip = self
-            .ts
-            .get_ips(None)
-            .map_err(|e| GossipError::NetworkError(e))?;
+        let ip = self.ts.get_ips(None).map_err(GossipError::NetworkError)?;
 
         Ok(ip)
     }
 
+    /// Devices in the tailnet matching `selector`, as seed nodes.
     pub async fn get_peers(&self) -> Result<Vec<Node>, GossipError> {
-        let devices = self
-            .api
-            .list_devices()
-            .await
-            .map_err(|e| GossipError::PeerError(e))?;
+        self.discovery().peers().await
+    }
+
+    /// Device query with the current `selector`, for polling the
+    /// tailnet with `GossipHandle::discover` once the transport has
+    /// been handed to `start`.
+    pub fn discovery(&self) -> TailscaleDiscovery {
+        TailscaleDiscovery {
+            api: self.api.clone(),
+            selector: self.selector.clone(),
+            hostname: self.id.clone(),
+            ports: Arc::clone(&self.ports),
+        }
+    }
+}
 
-        let prefix = format!("{}-", self.gossip_config.prefix);
+/// Lists the tailnet's gossip peers from the Tailscale device API.
+#[derive(Clone)]
+pub struct TailscaleDiscovery {
+    api: DeviceApi,
+    selector: PeerSelector,
+    /// Our own hostname, excluded from the results.
+    hostname: String,
+    /// Shared with the transport, which labels accepted connections
+    /// with the ports recorded here.
+    ports: Arc<PeerPorts>,
+}
+
+impl TailscaleDiscovery {
+    /// Devices matching the selector, as seed nodes. Each is reached
+    /// on its advertised port, or our `gossip_port`.
+    pub async fn peers(&self) -> Result<Vec<Node>, GossipError> {
+        let devices = self.api.list_devices().await?;
 
         let nodes: Vec<Node> = devices
             .into_iter()
-            .filter(|d| d.hostname.starts_with(&prefix))
+            .filter(|d| d.hostname != self.hostname && self.selector.matches(d))
             .filter_map(|d| {
-                let name = d.hostname;
-                let node_id = hash_node_name(&name);
-                let ip_str = d.addresses.first()?;
-                let ip = extract_ipv4(ip_str).ok()?;
-                let addr = SocketAddr::from((ip, 42069));
-                Some(Node::new(node_id, addr))
+                let node_id = hash_node_name(&d.hostname);
+                let ip =
+                    d.addresses.iter().find_map(|a| extract_ipv4(a).ok())?;
+                let port = self.ports.record(ip, &d);
+                Some(Node::new(node_id, SocketAddr::from((ip, port))))
             })
             .collect();
 
@@ -108,60 +359,291 @@ impl Tailscale {
     }
 }
 
+#[async_trait]
+impl Discovery for TailscaleDiscovery {
+    async fn discover(&self) -> Result<Vec<SocketAddr>, GossipError> {
+        let peers = self.peers().await?;
+        Ok(peers.into_iter().map(|n| n.addr).collect())
+    }
+}
+
+impl Tailscale {
+    /// Socket connected to `addr`, dialing one if none is cached.
+    /// Sockets idle for longer than `CONN_IDLE_TIMEOUT` are evicted.
+    fn conn(&self, addr: &str) -> Result<Arc<UdpSocket>, GossipError> {
+        let mut conns = self.conns.lock().unwrap();
+        conns.retain(|_, c| c.last_used.elapsed() < CONN_IDLE_TIMEOUT);
+
+        if let Some(conn) = conns.get_mut(addr) {
+            conn.last_used = Instant::now();
+            return Ok(Arc::clone(&conn.socket));
+        }
+
+        let fd = self
+            .ts
+            .dial("udp", addr)
+            .map_err(GossipError::NetworkError)?;
+        let socket = Arc::new(UdpSocket::from(fd));
+
+        conns.insert(
+            addr.to_string(),
+            CachedConn {
+                socket: Arc::clone(&socket),
+                last_used: Instant::now(),
+            },
+        );
+
+        Ok(socket)
+    }
+
+    fn forget_conn(&self, addr: &str) {
+        self.conns.lock().unwrap().remove(addr);
+    }
+}
+
+/// Accept connections until closed, handing each to its own reader.
+fn accept_loop(
+    ts: Arc<TSNet>,
+    listener: TailscaleListener,
+    ports: Arc<PeerPorts>,
+    buf_size: usize,
+    tx: mpsc::UnboundedSender<Packet>,
+    closed: Arc<AtomicBool>,
+) {
+    loop {
+        let conn = ts.accept(listener.as_fd());
+
+        if closed.load(Ordering::SeqCst) {
+            break;
+        }
+
+        let conn = match conn {
+            Ok(conn) => conn,
+            Err(e) => {
+                error!("Error accepting connection: {}", e);
+                continue;
+            }
+        };
+
+        info!("accepted connection");
+
+        let remote_addr = match remote_addr(&ts, &conn, &listener, &ports) {
+            Ok(addr) => addr,
+            Err(e) => {
+                error!("Error reading remote address: {}", e);
+                continue;
+            }
+        };
+
+        let socket = UdpSocket::from(conn);
+        let tx = tx.clone();
+        let closed = Arc::clone(&closed);
+        thread::spawn(move || {
+            read_loop(socket, remote_addr, buf_size, tx, closed)
+        });
+    }
+}
+
+/// Accept TCP connections until closed, queueing them for
+/// `accept_stream`.
+fn accept_streams(
+    ts: Arc<TSNet>,
+    listener: TailscaleListener,
+    ports: Arc<PeerPorts>,
+    tx: mpsc::UnboundedSender<Incoming>,
+    closed: Arc<AtomicBool>,
+) {
+    loop {
+        let conn = ts.accept(listener.as_fd());
+
+        if closed.load(Ordering::SeqCst) {
+            break;
+        }
+
+        let conn = match conn {
+            Ok(conn) => conn,
+            Err(e) => {
+                error!("Error accepting stream: {}", e);
+                continue;
+            }
+        };
+
+        let remote_addr = match remote_addr(&ts, &conn, &listener, &ports) {
+            Ok(addr) => addr,
+            Err(e) => {
+                error!("Error reading remote address: {}", e);
+                continue;
+            }
+        };
+
+        if tx.send((conn, remote_addr)).is_err() {
+            break;
+        }
+    }
+}
+
+/// Gossip address of the peer on an accepted connection: its
+/// tailnet IP and the port it advertises, since the connection itself
+/// comes from an ephemeral port nobody reads replies on.
+fn remote_addr(
+    ts: &TSNet,
+    conn: &OwnedFd,
+    listener: &TailscaleListener,
+    ports: &PeerPorts,
+) -> Result<SocketAddr, GossipError> {
+    let addr = ts
+        .get_remote_addr(conn.as_fd(), listener.as_fd())
+        .map_err(GossipError::NetworkError)?;
+
+    Ok(ports.addr(extract_ipv4(&addr)?))
+}
+
+/// Connection handed over by tsnet as a tokio stream. tsnet proxies
+/// each connection through one end of a Unix socket pair.
+fn into_stream(fd: OwnedFd) -> Result<Box<dyn GossipStream>, GossipError> {
+    let stream = UnixStream::from(fd);
+    stream.set_nonblocking(true)?;
+    Ok(Box::new(tokio::net::UnixStream::from_std(stream)?))
+}
+
+/// Read datagrams from one accepted connection until it errors,
+/// sits idle for `CONN_IDLE_TIMEOUT`, or the transport is closed.
+fn read_loop(
+    socket: UdpSocket,
+    remote_addr: SocketAddr,
+    buf_size: usize,
+    tx: mpsc::UnboundedSender<Packet>,
+    closed: Arc<AtomicBool>,
+) {
+    if let Err(e) = socket.set_read_timeout(Some(READ_TIMEOUT)) {
+        error!("Error setting read timeout: {}", e);
+        return;
+    }
+
+    let mut buf = vec![0; buf_size];
+    let mut last_read = Instant::now();
+
+    while !closed.load(Ordering::SeqCst) {
+        match socket.recv(&mut buf) {
+            Ok(len) => {
+                last_read = Instant::now();
+                if tx.send((buf[..len].to_vec(), remote_addr)).is_err() {
+                    break;
+                }
+            }
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    ErrorKind::WouldBlock | ErrorKind::TimedOut
+                ) =>
+            {
+                if last_read.elapsed() > CONN_IDLE_TIMEOUT {
+                    break;
+                }
+            }
+            Err(e) => {
+                debug!("Connection from {} closed: {}", remote_addr, e);
+                break;
+            }
+        }
+    }
+}
+
 #[async_trait]
 impl GossipTransport for Tailscale {
+    /// Send over a cached socket, re-dialing once if it has gone bad.
     async fn write(
         &self,
-        buf: &heapless::Vec<u8, MAX_PAYLOAD_SIZE>,
+        buf: &[u8],
         addr: String,
     ) -> Result<usize, GossipError> {
-        let fd = self
-            .ts
-            .dial("udp", addr.as_str())
-            .map_err(GossipError::NetworkError)?;
-        let socket = UdpSocket::from(fd);
-        socket.send(buf).map_err(GossipError::Io)
+        match self.conn(&addr)?.send(buf) {
+            Ok(len) => Ok(len),
+            Err(e) => {
+                debug!("Error writing to {}, redialing: {}", addr, e);
+                self.forget_conn(&addr);
+                self.conn(&addr)?.send(buf).map_err(GossipError::Io)
+            }
+        }
     }
 
     async fn recv_from(
         &self,
         buf: &mut Vec<u8>,
     ) -> Result<(usize, SocketAddr), GossipError> {
-        if self.listener.is_none() {
+        let mut packets = self.packets.lock().await;
+
+        let Some(packets) = packets.as_mut() else {
             return Err(GossipError::NetworkError(
                 "you must call listen first".to_string(),
             ));
-        }
+        };
 
-        let listener = self.listener.as_ref().unwrap();
+        let (data, remote_addr) = packets.recv().await.ok_or_else(|| {
+            GossipError::NetworkError("listener closed".to_string())
+        })?;
 
-        let conn = self
-            .ts
-            .accept(listener.as_fd())
-            .map_err(|e| GossipError::NetworkError(e))?;
+        let len = data.len().min(buf.len());
+        buf[..len].copy_from_slice(&data[..len]);
+        Ok((len, remote_addr))
+    }
 
-        info!("accepted connection");
+    async fn get_ip(&self) -> Result<String, GossipError> {
+        self.get_ip().await
+    }
+
+    fn supports_streams(&self) -> bool {
+        true
+    }
 
-        let remote_addr = self
+    async fn connect_stream(
+        &self,
+        addr: SocketAddr,
+    ) -> Result<Box<dyn GossipStream>, GossipError> {
+        let fd = self
             .ts
-            .get_remote_addr(conn.as_fd(), listener.as_fd())
-            .map_err(|e| GossipError::NetworkError(e))?;
+            .dial("tcp", &addr.to_string())
+            .map_err(GossipError::NetworkError)?;
+        into_stream(fd)
+    }
 
-        let socket = UdpSocket::from(conn);
+    async fn accept_stream(
+        &self,
+    ) -> Result<(Box<dyn GossipStream>, SocketAddr), GossipError> {
+        let mut streams = self.streams.lock().await;
 
-        let remote_addr = extract_ipv4(&remote_addr)?;
-        let remote_addr =
-            SocketAddr::from((remote_addr, self.gossip_config.gossip_port));
+        let Some(streams) = streams.as_mut() else {
+            return Err(GossipError::NetworkError(
+                "you must call listen first".to_string(),
+            ));
+        };
 
-        let len = socket
-            .recv(buf)
-            .map_err(|e| GossipError::NetworkError(e.to_string()))?;
+        let (fd, remote_addr) = streams.recv().await.ok_or_else(|| {
+            GossipError::NetworkError("listener closed".to_string())
+        })?;
 
-        Ok((len, remote_addr))
+        Ok((into_stream(fd)?, remote_addr))
     }
 
-    async fn get_ip(&self) -> Result<String, GossipError> {
-        self.get_ip().await
+    /// Stop the accept loops and readers and drop cached sockets.
+    async fn close(&self) -> Result<(), GossipError> {
+        self.closed.store(true, Ordering::SeqCst);
+        self.conns.lock().unwrap().clear();
+
+        // wake the accept loop blocked on the listener so it can return
+        if let Some(listen_addr) = &self.listen_addr {
+            let fd = self
+                .ts
+                .dial("udp", listen_addr)
+                .map_err(GossipError::NetworkError)?;
+            UdpSocket::from(fd).send(&[0])?;
+
+            self.ts
+                .dial("tcp", listen_addr)
+                .map_err(GossipError::NetworkError)?;
+        }
+
+        Ok(())
     }
 }
 // impl GossipSocket for Tailscale {
@@ -0,0 +1,71 @@
+use std::collections::HashMap;
+
+use serde::de::DeserializeOwned;
+use tokio::sync::broadcast;
+
+use crate::constants::SUBSCRIBER_CAPACITY;
+use crate::error::GossipError;
+use crate::node::NodeId;
+
+/// Message published to a topic, delivered to the node's
+/// subscribers of that topic.
+#[derive(Debug, Clone)]
+pub struct TopicMessage {
+    pub from_id: NodeId,
+    pub topic: String,
+    pub payload: Vec<u8>,
+}
+
+impl TopicMessage {
+    /// Decode the payload with postcard.
+    pub fn decode<T: DeserializeOwned>(&self) -> Result<T, GossipError> {
+        Ok(postcard::from_bytes(&self.payload)?)
+    }
+}
+
+/// The topics the local node subscribes to, each with its own
+/// channel so subscribers only see the topics they asked for.
+#[derive(Default)]
+pub struct Subscriptions {
+    topics: HashMap<String, broadcast::Sender<TopicMessage>>,
+}
+
+impl Subscriptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Receive messages published to `topic`. Returns whether this is
+    /// a new subscription along with the receiver.
+    pub fn subscribe(
+        &mut self,
+        topic: &str,
+    ) -> (bool, broadcast::Receiver<TopicMessage>) {
+        match self.topics.get(topic) {
+            Some(tx) => (false, tx.subscribe()),
+            None => {
+                let (tx, rx) = broadcast::channel(SUBSCRIBER_CAPACITY);
+                self.topics.insert(topic.to_string(), tx);
+                (true, rx)
+            }
+        }
+    }
+
+    /// Drop a subscription, closing its receivers.
+    /// Returns whether there was one.
+    pub fn unsubscribe(&mut self, topic: &str) -> bool {
+        self.topics.remove(topic).is_some()
+    }
+
+    /// Hand a message to the subscribers of its topic.
+    /// Returns false if we don't subscribe to it.
+    pub fn deliver(&self, msg: TopicMessage) -> bool {
+        match self.topics.get(&msg.topic) {
+            Some(tx) => {
+                let _ = tx.send(msg);
+                true
+            }
+            None => false,
+        }
+    }
+}
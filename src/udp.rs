@@ -0,0 +1,89 @@
+use std::net::{IpAddr, SocketAddr};
+
+use async_trait::async_trait;
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+
+use crate::GossipConfig;
+use crate::error::GossipError;
+use crate::protocol::GossipTransport;
+use crate::stream::GossipStream;
+
+/// `GossipTransport` over a plain UDP socket, for hosts outside a
+/// tailnet. One socket is bound for the life of the transport and used
+/// for both sending and receiving; streams are TCP connections to a
+/// listener on the same port.
+pub struct UdpTransport {
+    socket: UdpSocket,
+    listener: TcpListener,
+}
+
+impl UdpTransport {
+    /// Bind `ip_address:gossip_port`; either an IPv4 or IPv6 address.
+    pub async fn bind(
+        gossip_config: &GossipConfig,
+    ) -> Result<Self, GossipError> {
+        let ip = gossip_config.ip_address.parse::<IpAddr>().map_err(|_| {
+            GossipError::IpAddressError(gossip_config.ip_address.clone())
+        })?;
+
+        let socket =
+            UdpSocket::bind(SocketAddr::new(ip, gossip_config.gossip_port))
+                .await?;
+        // bind the port the socket got, in case it was picked for us
+        let listener = TcpListener::bind(socket.local_addr()?).await?;
+
+        Ok(Self { socket, listener })
+    }
+
+    /// Address the socket is bound to; useful when binding port 0.
+    pub fn local_addr(&self) -> Result<SocketAddr, GossipError> {
+        Ok(self.socket.local_addr()?)
+    }
+}
+
+#[async_trait]
+impl GossipTransport for UdpTransport {
+    async fn write(
+        &self,
+        buf: &[u8],
+        addr: String,
+    ) -> Result<usize, GossipError> {
+        let addr = addr
+            .parse::<SocketAddr>()
+            .map_err(|_| GossipError::IpAddressError(addr))?;
+
+        Ok(self.socket.send_to(buf, addr).await?)
+    }
+
+    async fn recv_from(
+        &self,
+        buf: &mut Vec<u8>,
+    ) -> Result<(usize, SocketAddr), GossipError> {
+        Ok(self.socket.recv_from(buf).await?)
+    }
+
+    async fn get_ip(&self) -> Result<String, GossipError> {
+        Ok(self.local_addr()?.ip().to_string())
+    }
+
+    fn supports_streams(&self) -> bool {
+        true
+    }
+
+    async fn connect_stream(
+        &self,
+        addr: SocketAddr,
+    ) -> Result<Box<dyn GossipStream>, GossipError> {
+        let stream = TcpStream::connect(addr).await?;
+        stream.set_nodelay(true)?;
+        Ok(Box::new(stream))
+    }
+
+    async fn accept_stream(
+        &self,
+    ) -> Result<(Box<dyn GossipStream>, SocketAddr), GossipError> {
+        let (stream, addr) = self.listener.accept().await?;
+        stream.set_nodelay(true)?;
+        Ok((Box::new(stream), addr))
+    }
+}
@@ -2,8 +2,10 @@ use std::net::{IpAddr, Ipv4Addr};
 
 use human_ids::{Options, generate};
 use nanoid::nanoid;
+use sha2::{Digest, Sha256};
 
 use crate::error::GossipError;
+use crate::node::NodeId;
 
 const ALPHABET: [char; 26] = [
     'a', 'b', 'c', 'd', 'e', 'f', 'g', 'h', 'i', 'j', 'k', 'l', 'm', 'n', 'o',
@@ -39,8 +41,14 @@ pub fn extract_ipv4(input: &str) -> Result<Ipv4Addr, GossipError> {
     }
 }
 
-pub fn hash_node_name(name: &str) -> u32 {
-    name.as_bytes()
-        .iter()
-        .fold(0u32, |acc, &b| acc.wrapping_add(b as u32))
+/// Derive a node's ID from its name: the first 128 bits of its
+/// SHA-256 digest. Stable across platforms and releases, so every node
+/// derives the same ID for a given name, and infeasible to collide
+/// with another node's ID by picking a name.
+pub fn hash_node_name(name: &str) -> NodeId {
+    let digest = Sha256::digest(name.as_bytes());
+    let mut id = [0; 16];
+    id.copy_from_slice(&digest[..16]);
+
+    NodeId(u128::from_be_bytes(id))
 }
@@ -0,0 +1,310 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use gossip::discovery::{
+    CompositeDiscovery, Discovery, DnsDiscovery, FileDiscovery, StaticDiscovery,
+};
+use gossip::memory::MemoryNetwork;
+use gossip::{
+    GossipConfig, GossipError, GossipHandle, Handlers, MembershipEvent,
+    NodeStatus, start,
+};
+use hickory_proto::op::{Message, MessageType};
+use hickory_proto::rr::rdata::{A, SRV};
+use hickory_proto::rr::{Name, RData, Record, RecordType};
+use tokio::net::UdpSocket;
+use tokio::time::{sleep, timeout};
+
+fn addr(port: u16) -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], port))
+}
+
+fn config(name: &str, port: u16) -> GossipConfig {
+    GossipConfig {
+        node_name: name.to_string(),
+        gossip_port: port,
+        heartbeat_interval: Duration::from_millis(50),
+        gossip_interval: Duration::from_millis(100),
+        probe_interval: Duration::from_millis(100),
+        probe_timeout: Duration::from_millis(50),
+        discovery_interval: Duration::from_millis(100),
+        ..GossipConfig::default()
+    }
+}
+
+/// Start a node on `network` without any seeds.
+async fn spawn_node(
+    network: &MemoryNetwork,
+    name: &str,
+    port: u16,
+) -> GossipHandle {
+    let transport = network.transport(addr(port));
+    start(
+        config(name, port),
+        Box::new(transport),
+        HashMap::new(),
+        Handlers::new(),
+    )
+    .await
+    .unwrap()
+}
+
+async fn wait_online(handle: &GossipHandle, count: usize) {
+    timeout(Duration::from_secs(5), async {
+        loop {
+            let online = handle
+                .peers()
+                .await
+                .iter()
+                .filter(|p| p.status == NodeStatus::Online)
+                .count();
+            if online == count {
+                break;
+            }
+            sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("peers were not discovered");
+}
+
+/// Answer A queries with 127.0.0.1 and SRV queries with one record
+/// per port in `srv_ports`, all targeting `node.gossip.test.`.
+async fn dns_server(srv_ports: Vec<u16>) -> SocketAddr {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let local = socket.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        let mut buf = vec![0u8; 512];
+        loop {
+            let Ok((len, src)) = socket.recv_from(&mut buf).await else {
+                return;
+            };
+            let Ok(query) = Message::from_vec(&buf[..len]) else {
+                continue;
+            };
+
+            let mut response = Message::new();
+            response
+                .set_id(query.id())
+                .set_message_type(MessageType::Response)
+                .set_recursion_available(true)
+                .add_queries(query.queries().to_vec());
+
+            for q in query.queries() {
+                match q.query_type() {
+                    RecordType::A => {
+                        response.add_answer(Record::from_rdata(
+                            q.name().clone(),
+                            60,
+                            RData::A(A::new(127, 0, 0, 1)),
+                        ));
+                    }
+                    RecordType::SRV => {
+                        let target =
+                            Name::from_ascii("node.gossip.test.").unwrap();
+                        for port in &srv_ports {
+                            response.add_answer(Record::from_rdata(
+                                q.name().clone(),
+                                60,
+                                RData::SRV(SRV::new(
+                                    0,
+                                    0,
+                                    *port,
+                                    target.clone(),
+                                )),
+                            ));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+
+            let _ = socket.send_to(&response.to_vec().unwrap(), src).await;
+        }
+    });
+
+    local
+}
+
+#[tokio::test]
+async fn static_discovery_joins_nodes() {
+    let network = MemoryNetwork::with_seed(41);
+    let mut a = spawn_node(&network, "node-a", 9001).await;
+    let b = spawn_node(&network, "node-b", 9002).await;
+    let c = spawn_node(&network, "node-c", 9003).await;
+
+    a.discover(StaticDiscovery::new(vec![addr(9002), addr(9003)]));
+
+    wait_online(&a, 2).await;
+    wait_online(&b, 2).await;
+    wait_online(&c, 2).await;
+}
+
+#[tokio::test]
+async fn file_discovery_follows_changes() {
+    let dir = tempdir::TempDir::new("gossip-discovery").unwrap();
+    let path = dir.path().join("peers.json");
+    std::fs::write(&path, r#"{"peers": ["127.0.0.1:9012"]}"#).unwrap();
+
+    let network = MemoryNetwork::with_seed(43);
+    let mut a = spawn_node(&network, "node-a", 9011).await;
+    let _b = spawn_node(&network, "node-b", 9012).await;
+    let c = spawn_node(&network, "node-c", 9013).await;
+
+    a.discover(FileDiscovery::new(&path));
+    wait_online(&a, 1).await;
+    assert!(c.peers().await.is_empty());
+
+    std::fs::write(&path, r#"{"peers": ["127.0.0.1:9012", "127.0.0.1:9013"]}"#)
+        .unwrap();
+    wait_online(&c, 2).await;
+}
+
+#[tokio::test]
+async fn file_discovery_reads_toml() {
+    let dir = tempdir::TempDir::new("gossip-discovery").unwrap();
+    let path = dir.path().join("peers.toml");
+    std::fs::write(&path, "peers = [\"127.0.0.1:9021\", \"localhost:9022\"]")
+        .unwrap();
+
+    let addrs = FileDiscovery::new(&path).discover().await.unwrap();
+    assert!(addrs.contains(&addr(9021)));
+    assert!(addrs.iter().any(|a| a.port() == 9022));
+}
+
+#[tokio::test]
+async fn dns_discovery_resolves_a_records() {
+    let nameserver = dns_server(vec![]).await;
+
+    let addrs = DnsDiscovery::ip("nodes.gossip.test.", 9031)
+        .nameserver(nameserver)
+        .discover()
+        .await
+        .unwrap();
+
+    assert_eq!(addrs, vec![addr(9031)]);
+}
+
+#[tokio::test]
+async fn dns_discovery_resolves_srv_records() {
+    let nameserver = dns_server(vec![9041, 9042]).await;
+
+    let mut addrs = DnsDiscovery::srv("_gossip._udp.gossip.test.")
+        .nameserver(nameserver)
+        .discover()
+        .await
+        .unwrap();
+    addrs.sort();
+
+    assert_eq!(addrs, vec![addr(9041), addr(9042)]);
+}
+
+#[tokio::test]
+async fn composite_discovery_merges_sources_and_skips_failures() {
+    let mut discovery = CompositeDiscovery::new();
+    discovery.add(StaticDiscovery::new(vec![addr(9051), addr(9052)]));
+    discovery.add(StaticDiscovery::new(vec![addr(9052), addr(9053)]));
+    discovery.add(FileDiscovery::new("/nonexistent/peers.json"));
+
+    let mut addrs = discovery.discover().await.unwrap();
+    addrs.sort();
+    assert_eq!(addrs, vec![addr(9051), addr(9052), addr(9053)]);
+
+    let mut failing = CompositeDiscovery::new();
+    failing.add(FileDiscovery::new("/nonexistent/peers.json"));
+    assert!(failing.discover().await.is_err());
+}
+
+/// Returns whatever the test last set, like a source whose answers
+/// come and go.
+#[derive(Clone, Default)]
+struct SwitchedDiscovery {
+    addrs: Arc<Mutex<Vec<SocketAddr>>>,
+}
+
+impl SwitchedDiscovery {
+    fn set(&self, addrs: Vec<SocketAddr>) {
+        *self.addrs.lock().unwrap() = addrs;
+    }
+}
+
+#[async_trait]
+impl Discovery for SwitchedDiscovery {
+    async fn discover(&self) -> Result<Vec<SocketAddr>, GossipError> {
+        Ok(self.addrs.lock().unwrap().clone())
+    }
+}
+
+#[tokio::test]
+async fn live_peer_missing_from_a_poll_stays_online() {
+    let network = MemoryNetwork::with_seed(47);
+    let mut a = spawn_node(&network, "node-a", 9061).await;
+    let b = spawn_node(&network, "node-b", 9062).await;
+
+    let discovery = SwitchedDiscovery::default();
+    discovery.set(vec![addr(9062)]);
+    a.discover(discovery.clone());
+    wait_online(&a, 1).await;
+
+    // the source loses node-b for a few polls
+    let mut events = a.events();
+    discovery.set(vec![]);
+    sleep(Duration::from_millis(500)).await;
+    discovery.set(vec![addr(9062)]);
+    sleep(Duration::from_millis(200)).await;
+
+    let b_id = b.local_node().id;
+    while let Ok(event) = events.try_recv() {
+        assert!(
+            !matches!(event, MembershipEvent::Left(ref n) if n.id == b_id),
+            "node-b was marked as left"
+        );
+    }
+    wait_online(&a, 1).await;
+}
+
+#[tokio::test]
+async fn removed_device_is_suspected_before_its_turn_to_be_probed() {
+    let network = MemoryNetwork::with_seed(53);
+    // so rarely that the regular probe can't be what notices
+    let a_config = GossipConfig {
+        probe_interval: Duration::from_secs(5),
+        ..config("node-a", 9071)
+    };
+    let mut a = start(
+        a_config,
+        Box::new(network.transport(addr(9071))),
+        HashMap::new(),
+        Handlers::new(),
+    )
+    .await
+    .unwrap();
+    let b = spawn_node(&network, "node-b", 9072).await;
+
+    let discovery = SwitchedDiscovery::default();
+    discovery.set(vec![addr(9072)]);
+    a.discover(discovery.clone());
+    wait_online(&a, 1).await;
+
+    // the device is deleted: gone from the source and unreachable
+    let mut events = a.events();
+    network.partition(&[addr(9071)], &[addr(9072)]);
+    discovery.set(vec![]);
+
+    let b_id = b.local_node().id;
+    timeout(Duration::from_secs(1), async {
+        loop {
+            if let Ok(MembershipEvent::Suspected(node)) = events.recv().await
+                && node.id == b_id
+            {
+                break;
+            }
+        }
+    })
+    .await
+    .expect("node-b was not suspected");
+}
@@ -0,0 +1,1041 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use ed25519_dalek::SigningKey;
+use gossip::constants::{MAX_FRAME_SIZE, MAX_METADATA_SIZE, MIN_DATAGRAM_SIZE};
+use gossip::memory::{LinkConditions, MemoryNetwork};
+use gossip::message::{GossipMessage, MessageId, MessageKind, SyncState};
+use gossip::stream::{read_frame, write_frame};
+use gossip::{
+    GossipConfig, GossipError, GossipHandle, GossipTransport, Handlers,
+    Keyring, MembershipEvent, Node, NodeStatus, start, util::hash_node_name,
+};
+use tokio::time::{sleep, timeout};
+
+fn addr(port: u16) -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], port))
+}
+
+fn config(name: &str, port: u16) -> GossipConfig {
+    GossipConfig {
+        node_name: name.to_string(),
+        gossip_port: port,
+        heartbeat_interval: Duration::from_millis(50),
+        gossip_interval: Duration::from_millis(100),
+        offline_timeout: Duration::from_secs(2),
+        probe_interval: Duration::from_millis(100),
+        probe_timeout: Duration::from_millis(50),
+        suspicion_timeout: Duration::from_millis(300),
+        ..GossipConfig::default()
+    }
+}
+
+/// Start a node on `network` that only knows about `seeds`.
+async fn spawn_node(
+    network: &MemoryNetwork,
+    name: &str,
+    port: u16,
+    seeds: &[(&str, u16)],
+) -> GossipHandle {
+    spawn_with_config(network, config(name, port), seeds).await
+}
+
+async fn spawn_with_config(
+    network: &MemoryNetwork,
+    config: GossipConfig,
+    seeds: &[(&str, u16)],
+) -> GossipHandle {
+    spawn_with_handlers(network, config, seeds, Handlers::new()).await
+}
+
+async fn spawn_with_handlers(
+    network: &MemoryNetwork,
+    config: GossipConfig,
+    seeds: &[(&str, u16)],
+    handlers: Handlers,
+) -> GossipHandle {
+    let port = config.gossip_port;
+    let seed_peers = seeds
+        .iter()
+        .map(|(name, port)| {
+            let id = hash_node_name(name);
+            (id, Node::new(id, addr(*port)))
+        })
+        .collect::<HashMap<_, _>>();
+
+    let transport = network.transport(addr(port));
+    start(config, Box::new(transport), seed_peers, handlers)
+        .await
+        .unwrap()
+}
+
+/// Poll until every handle knows every other node as online.
+async fn converged(handles: &[&GossipHandle]) -> bool {
+    for handle in handles {
+        let online = handle
+            .peers()
+            .await
+            .iter()
+            .filter(|p| p.status == NodeStatus::Online)
+            .count();
+
+        if online != handles.len() - 1 {
+            return false;
+        }
+    }
+
+    true
+}
+
+async fn wait_converged(handles: &[&GossipHandle]) {
+    timeout(Duration::from_secs(5), async {
+        while !converged(handles).await {
+            sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("cluster did not converge");
+}
+
+#[tokio::test]
+async fn membership_converges_from_a_chain_of_seeds() {
+    let network = MemoryNetwork::with_seed(7);
+    let a = spawn_node(&network, "node-a", 7001, &[]).await;
+    let b = spawn_node(&network, "node-b", 7002, &[("node-a", 7001)]).await;
+    let c = spawn_node(&network, "node-c", 7003, &[("node-b", 7002)]).await;
+
+    wait_converged(&[&a, &b, &c]).await;
+}
+
+#[tokio::test]
+async fn membership_converges_over_a_lossy_network() {
+    let network = MemoryNetwork::with_seed(11);
+    network.set_conditions(LinkConditions {
+        latency: Duration::from_millis(2),
+        jitter: Duration::from_millis(5),
+        drop_rate: 0.1,
+        duplicate_rate: 0.1,
+    });
+
+    let a = spawn_node(&network, "node-a", 7011, &[]).await;
+    let b = spawn_node(&network, "node-b", 7012, &[("node-a", 7011)]).await;
+    let c = spawn_node(&network, "node-c", 7013, &[("node-a", 7011)]).await;
+
+    wait_converged(&[&a, &b, &c]).await;
+}
+
+#[tokio::test]
+async fn broadcast_reaches_subscribers() {
+    let network = MemoryNetwork::with_seed(3);
+    let a = spawn_node(&network, "node-a", 7021, &[("node-b", 7022)]).await;
+    let b = spawn_node(&network, "node-b", 7022, &[("node-a", 7021)]).await;
+    wait_converged(&[&a, &b]).await;
+
+    let mut messages = b.subscribe();
+    a.broadcast(1, "hello").await.unwrap();
+
+    let msg = timeout(Duration::from_secs(1), messages.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(msg.kind, MessageKind::Application(1));
+    assert_eq!(msg.decode::<String>().unwrap(), "hello");
+}
+
+#[tokio::test]
+async fn partitioned_node_is_declared_offline() {
+    let network = MemoryNetwork::with_seed(5);
+    let a = spawn_node(&network, "node-a", 7031, &[]).await;
+    let b = spawn_node(&network, "node-b", 7032, &[("node-a", 7031)]).await;
+    let c = spawn_node(&network, "node-c", 7033, &[("node-a", 7031)]).await;
+    wait_converged(&[&a, &b, &c]).await;
+
+    let mut events = a.events();
+    network.partition(&[addr(7031), addr(7032)], &[addr(7033)]);
+
+    let c_id = c.local_node().id;
+    timeout(Duration::from_secs(5), async {
+        loop {
+            if let Ok(MembershipEvent::Offline(node)) = events.recv().await
+                && node.id == c_id
+            {
+                break;
+            }
+        }
+    })
+    .await
+    .expect("node-c was not declared offline");
+
+    network.heal();
+    wait_converged(&[&a, &b, &c]).await;
+}
+
+#[tokio::test]
+async fn shutdown_announces_leave() {
+    let network = MemoryNetwork::with_seed(9);
+    let a = spawn_node(&network, "node-a", 7041, &[("node-b", 7042)]).await;
+    let b = spawn_node(&network, "node-b", 7042, &[("node-a", 7041)]).await;
+    wait_converged(&[&a, &b]).await;
+
+    let b_id = b.local_node().id;
+    b.shutdown().await.unwrap();
+
+    sleep(Duration::from_millis(100)).await;
+    let peer = a.peers().await.into_iter().find(|p| p.id == b_id).unwrap();
+    assert_eq!(peer.status, NodeStatus::Left);
+}
+
+#[tokio::test]
+async fn broadcast_is_delivered_once() {
+    let network = MemoryNetwork::with_seed(13);
+    let a = spawn_node(&network, "node-a", 7051, &[]).await;
+    let b = spawn_node(&network, "node-b", 7052, &[("node-a", 7051)]).await;
+    let c = spawn_node(&network, "node-c", 7053, &[("node-a", 7051)]).await;
+    wait_converged(&[&a, &b, &c]).await;
+
+    network.set_conditions(LinkConditions {
+        duplicate_rate: 1.0,
+        ..LinkConditions::default()
+    });
+
+    let mut messages = c.subscribe();
+    a.broadcast(1, "once").await.unwrap();
+
+    sleep(Duration::from_millis(300)).await;
+    let mut delivered = 0;
+    while messages.try_recv().is_ok() {
+        delivered += 1;
+    }
+    assert_eq!(delivered, 1);
+}
+
+#[tokio::test]
+async fn large_broadcast_is_reassembled() {
+    let network = MemoryNetwork::with_seed(17);
+    network.set_streams(false);
+    let a = spawn_node(&network, "node-a", 7061, &[]).await;
+    let b = spawn_node(&network, "node-b", 7062, &[("node-a", 7061)]).await;
+    let c = spawn_node(&network, "node-c", 7063, &[("node-a", 7061)]).await;
+    wait_converged(&[&a, &b, &c]).await;
+
+    network.set_conditions(LinkConditions {
+        jitter: Duration::from_millis(5),
+        ..LinkConditions::default()
+    });
+
+    let blob = "x".repeat(10_000);
+    let mut messages = c.subscribe();
+    a.broadcast(1, &blob).await.unwrap();
+
+    let msg = timeout(Duration::from_secs(1), messages.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(msg.kind, MessageKind::Application(1));
+    assert_eq!(msg.decode::<String>().unwrap(), blob);
+}
+
+#[tokio::test]
+async fn large_broadcast_is_reassembled_past_the_first_hop() {
+    let network = MemoryNetwork::with_seed(103);
+    network.set_streams(false);
+    let chain = |name: &str, port: u16| GossipConfig {
+        fanout: 1,
+        message_ttl: 4,
+        ..config(name, port)
+    };
+    let a = spawn_with_config(&network, chain("node-a", 7261), &[]).await;
+    let b =
+        spawn_with_config(&network, chain("node-b", 7262), &[("node-a", 7261)])
+            .await;
+    let c =
+        spawn_with_config(&network, chain("node-c", 7263), &[("node-b", 7262)])
+            .await;
+    let d =
+        spawn_with_config(&network, chain("node-d", 7264), &[("node-c", 7263)])
+            .await;
+    wait_converged(&[&a, &b, &c, &d]).await;
+
+    let blob = "x".repeat(10_000);
+    let mut subscribers = [b.subscribe(), c.subscribe(), d.subscribe()];
+    a.broadcast(1, &blob).await.unwrap();
+    sleep(Duration::from_secs(1)).await;
+
+    // node-a sends to one peer, which relays the whole message on
+    let mut delivered = 0;
+    for messages in &mut subscribers {
+        while let Ok(msg) = messages.try_recv() {
+            assert_eq!(msg.decode::<String>().unwrap(), blob);
+            delivered += 1;
+        }
+    }
+    assert!(delivered >= 2, "only {} node(s) got the message", delivered);
+}
+
+#[tokio::test]
+async fn oversized_broadcast_is_rejected() {
+    let network = MemoryNetwork::with_seed(19);
+    let a = spawn_node(&network, "node-a", 7071, &[]).await;
+
+    let blob = vec![0u8; GossipConfig::default().max_message_size + 1];
+    let result = a.broadcast(1, &blob).await;
+    assert!(matches!(result, Err(GossipError::PayloadTooLarge(_))));
+}
+
+#[tokio::test]
+async fn small_datagrams_still_carry_membership_and_messages() {
+    let network = MemoryNetwork::with_seed(23);
+    let mut handles = vec![];
+    for (i, port) in (7081..7087).enumerate() {
+        let config = GossipConfig {
+            max_datagram_size: 512,
+            ..config(&format!("node-{}", i), port)
+        };
+        handles.push(
+            spawn_with_config(&network, config, &[("node-0", 7081)]).await,
+        );
+    }
+    wait_converged(&handles.iter().collect::<Vec<_>>()).await;
+
+    let blob = "y".repeat(2_000);
+    let mut messages = handles[5].subscribe();
+    handles[0].broadcast(1, &blob).await.unwrap();
+
+    let msg = timeout(Duration::from_secs(1), messages.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(msg.decode::<String>().unwrap(), blob);
+}
+
+#[tokio::test]
+async fn tiny_datagram_size_is_rejected() {
+    let network = MemoryNetwork::with_seed(29);
+    let config = GossipConfig {
+        max_datagram_size: 64,
+        ..config("node-a", 7091)
+    };
+
+    let result = start(
+        config,
+        Box::new(network.transport(addr(7091))),
+        HashMap::new(),
+        Handlers::new(),
+    )
+    .await;
+    assert!(matches!(result, Err(GossipError::InvalidConfig(_))));
+}
+
+fn encrypted(name: &str, port: u16, key: [u8; 32]) -> GossipConfig {
+    GossipConfig {
+        keyring: Some(Keyring::new(key)),
+        ..config(name, port)
+    }
+}
+
+#[tokio::test]
+async fn encrypted_cluster_rejects_outsiders() {
+    let network = MemoryNetwork::with_seed(31);
+    let key = [7; 32];
+    let a =
+        spawn_with_config(&network, encrypted("node-a", 7101, key), &[]).await;
+    let b = spawn_with_config(
+        &network,
+        encrypted("node-b", 7102, key),
+        &[("node-a", 7101)],
+    )
+    .await;
+    wait_converged(&[&a, &b]).await;
+
+    let outsider = spawn_with_config(
+        &network,
+        encrypted("node-c", 7103, [9; 32]),
+        &[("node-a", 7101)],
+    )
+    .await;
+    let plaintext =
+        spawn_node(&network, "node-d", 7104, &[("node-a", 7101)]).await;
+
+    // long enough for the outsiders to give up on their seed
+    sleep(Duration::from_secs(1)).await;
+    assert_eq!(a.peers().await.len(), 1);
+    assert!(a.unauthenticated_packets() > 0);
+    assert!(
+        outsider
+            .peers()
+            .await
+            .iter()
+            .all(|p| p.status != NodeStatus::Online)
+    );
+    assert!(
+        plaintext
+            .peers()
+            .await
+            .iter()
+            .all(|p| p.status != NodeStatus::Online)
+    );
+}
+
+#[tokio::test]
+async fn cluster_key_can_be_rotated() {
+    let network = MemoryNetwork::with_seed(37);
+    let (old, new) = ([1; 32], [2; 32]);
+    let a =
+        spawn_with_config(&network, encrypted("node-a", 7111, old), &[]).await;
+    let b = spawn_with_config(
+        &network,
+        encrypted("node-b", 7112, old),
+        &[("node-a", 7111)],
+    )
+    .await;
+    wait_converged(&[&a, &b]).await;
+
+    for handle in [&a, &b] {
+        handle.install_key(new).unwrap();
+    }
+    for handle in [&a, &b] {
+        handle.use_key(new).unwrap();
+    }
+    for handle in [&a, &b] {
+        handle.remove_key(old).unwrap();
+    }
+
+    let mut messages = b.subscribe();
+    a.broadcast(1, "rotated").await.unwrap();
+    let msg = timeout(Duration::from_secs(1), messages.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(msg.decode::<String>().unwrap(), "rotated");
+    assert!(a.remove_key(new).is_err());
+}
+
+#[tokio::test]
+async fn forged_messages_and_records_are_rejected() {
+    let network = MemoryNetwork::with_seed(41);
+    let a = spawn_node(&network, "node-a", 7121, &[]).await;
+    let b = spawn_node(&network, "node-b", 7122, &[("node-a", 7121)]).await;
+    wait_converged(&[&a, &b]).await;
+
+    let attacker = network.transport(addr(7123));
+    let key = SigningKey::from_bytes(&[5; 32]);
+    let b_node = b.local_node();
+
+    // a heartbeat claiming to come from node-b
+    let mut heartbeat = GossipMessage::heartbeat(
+        MessageId {
+            origin: b_node.id,
+            seq: u64::MAX,
+        },
+        b_node.incarnation + 1,
+        None,
+    );
+    heartbeat.sign(&key);
+
+    // an update from the attacker rewriting node-b's record
+    let mut own = Node::new(hash_node_name("attacker"), addr(7123));
+    own.sign(&key);
+    let mut record = b_node.clone();
+    record.addr = addr(7123);
+    record.incarnation += 1;
+    record.sign(&key);
+    let mut update = GossipMessage::update(
+        MessageId {
+            origin: own.id,
+            seq: 0,
+        },
+        &[own, record],
+        None,
+    );
+    update.sign(&key);
+
+    for msg in [heartbeat, update] {
+        let buf = GossipMessage::serialize(&msg).unwrap();
+        attacker.write(&buf, addr(7121).to_string()).await.unwrap();
+    }
+
+    sleep(Duration::from_millis(200)).await;
+    assert_eq!(a.unauthenticated_packets(), 1);
+    let peer = a
+        .peers()
+        .await
+        .into_iter()
+        .find(|p| p.id == b_node.id)
+        .unwrap();
+    assert_eq!(peer.addr, addr(7122));
+    assert_eq!(peer.public_key, b_node.public_key);
+}
+
+#[tokio::test]
+async fn metadata_and_tags_propagate() {
+    let network = MemoryNetwork::with_seed(43);
+    let config = GossipConfig {
+        metadata: BTreeMap::from([("role".to_string(), "db".to_string())]),
+        tags: BTreeSet::from(["primary".to_string()]),
+        ..config("node-a", 7131)
+    };
+    let a = spawn_with_config(&network, config, &[]).await;
+    let b = spawn_node(&network, "node-b", 7132, &[("node-a", 7131)]).await;
+    let c = spawn_node(&network, "node-c", 7133, &[("node-b", 7132)]).await;
+    wait_converged(&[&a, &b, &c]).await;
+
+    let primaries = timeout(Duration::from_secs(2), async {
+        loop {
+            let primaries = c.peers_with_tag("primary").await;
+            if !primaries.is_empty() {
+                break primaries;
+            }
+            sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("tags did not propagate");
+    assert_eq!(primaries.len(), 1);
+    assert_eq!(primaries[0].metadata["role"], "db");
+
+    a.set_metadata("version", "2").await.unwrap();
+    a.remove_tag("primary").await.unwrap();
+
+    let a_id = a.local_node().id;
+    timeout(Duration::from_secs(2), async {
+        loop {
+            let peers = c.peers().await;
+            let node = peers.iter().find(|p| p.id == a_id).unwrap();
+            if node.metadata.get("version").is_some_and(|v| v == "2")
+                && node.tags.is_empty()
+            {
+                break;
+            }
+            sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("metadata change did not propagate");
+    assert!(c.peers_with_tag("primary").await.is_empty());
+
+    let result = a.set_metadata("blob", "z".repeat(1024)).await;
+    assert!(matches!(result, Err(GossipError::PayloadTooLarge(_))));
+}
+
+/// Poll until `handle` reads `expected` under `key`.
+async fn wait_for_value(
+    handle: &GossipHandle,
+    key: &str,
+    expected: Option<&str>,
+) {
+    timeout(Duration::from_secs(5), async {
+        loop {
+            let value = handle.get::<String>(key).await.unwrap();
+            if value.as_deref() == expected {
+                break;
+            }
+            sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("key did not converge");
+}
+
+#[tokio::test]
+async fn kv_writes_and_deletes_replicate() {
+    let network = MemoryNetwork::with_seed(47);
+    let a = spawn_node(&network, "node-a", 7141, &[]).await;
+    let b = spawn_node(&network, "node-b", 7142, &[("node-a", 7141)]).await;
+    let c = spawn_node(&network, "node-c", 7143, &[("node-b", 7142)]).await;
+    wait_converged(&[&a, &b, &c]).await;
+
+    let mut changes = c.watch();
+    a.set("leader", "node-a").await.unwrap();
+    wait_for_value(&c, "leader", Some("node-a")).await;
+
+    let event = changes.recv().await.unwrap();
+    assert_eq!(event.key, "leader");
+    assert_eq!(event.decode::<String>().unwrap().as_deref(), Some("node-a"));
+
+    b.delete("leader").await.unwrap();
+    wait_for_value(&a, "leader", None).await;
+    wait_for_value(&c, "leader", None).await;
+
+    let result = a.set("blob", &"z".repeat(1024)).await;
+    assert!(matches!(result, Err(GossipError::PayloadTooLarge(_))));
+}
+
+#[tokio::test]
+async fn kv_converges_after_a_partition() {
+    let network = MemoryNetwork::with_seed(53);
+    let a = spawn_node(&network, "node-a", 7151, &[]).await;
+    let b = spawn_node(&network, "node-b", 7152, &[("node-a", 7151)]).await;
+    let c = spawn_node(&network, "node-c", 7153, &[("node-a", 7151)]).await;
+    wait_converged(&[&a, &b, &c]).await;
+
+    network.partition(&[addr(7151), addr(7152)], &[addr(7153)]);
+    a.set("endpoint", "10.0.0.1").await.unwrap();
+    c.set("leader", "node-c").await.unwrap();
+    sleep(Duration::from_millis(50)).await;
+    b.set("leader", "node-b").await.unwrap();
+    wait_for_value(&a, "leader", Some("node-b")).await;
+
+    // writes missed during the partition are repaired by anti-entropy,
+    // and the later write to a key wins everywhere
+    network.heal();
+    for handle in [&a, &b, &c] {
+        wait_for_value(handle, "endpoint", Some("10.0.0.1")).await;
+        wait_for_value(handle, "leader", Some("node-b")).await;
+    }
+}
+
+#[tokio::test]
+async fn push_pull_sync_repairs_lost_datagrams() {
+    let network = MemoryNetwork::with_seed(59);
+    let a = spawn_node(&network, "node-a", 7161, &[]).await;
+    let b = spawn_node(&network, "node-b", 7162, &[("node-a", 7161)]).await;
+    wait_converged(&[&a, &b]).await;
+
+    // from here on only streams get through
+    network.set_conditions(LinkConditions {
+        drop_rate: 1.0,
+        ..LinkConditions::default()
+    });
+    let c = spawn_node(&network, "node-c", 7163, &[("node-a", 7161)]).await;
+    a.set("leader", "node-a").await.unwrap();
+
+    wait_for_value(&b, "leader", Some("node-a")).await;
+    wait_for_value(&c, "leader", Some("node-a")).await;
+
+    let (b_id, c_id) = (b.local_node().id, c.local_node().id);
+    timeout(Duration::from_secs(5), async {
+        loop {
+            let b_knows_c = b.peers().await.iter().any(|p| p.id == c_id);
+            let c_knows_b = c.peers().await.iter().any(|p| p.id == b_id);
+            if b_knows_c && c_knows_b {
+                break;
+            }
+            sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("membership was not synced");
+}
+
+#[tokio::test]
+async fn large_broadcast_is_streamed() {
+    let network = MemoryNetwork::with_seed(61);
+    let a = spawn_node(&network, "node-a", 7171, &[]).await;
+    let b = spawn_node(&network, "node-b", 7172, &[("node-a", 7171)]).await;
+    let c = spawn_node(&network, "node-c", 7173, &[("node-a", 7171)]).await;
+    wait_converged(&[&a, &b, &c]).await;
+
+    // fragments would be lost; only streams get through
+    network.set_conditions(LinkConditions {
+        drop_rate: 1.0,
+        ..LinkConditions::default()
+    });
+
+    let blob = "x".repeat(10_000);
+    let mut b_messages = b.subscribe();
+    let mut c_messages = c.subscribe();
+    a.broadcast(1, &blob).await.unwrap();
+
+    for messages in [&mut b_messages, &mut c_messages] {
+        let msg = timeout(Duration::from_secs(1), messages.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(msg.decode::<String>().unwrap(), blob);
+    }
+}
+
+#[tokio::test]
+async fn streamed_message_from_an_unknown_sender_reaches_handlers() {
+    let network = MemoryNetwork::with_seed(97);
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    let mut handlers = Handlers::new();
+    handlers.register(1, move |from: Node, _msg: GossipMessage| {
+        let tx = tx.clone();
+        async move {
+            let _ = tx.send(from);
+        }
+    });
+    let b =
+        spawn_with_handlers(&network, config("node-b", 7242), &[], handlers)
+            .await;
+
+    // a peer node-b has never heard from streams it a message,
+    // introduced by its signed record
+    let peer = network.transport(addr(7241));
+    let key = SigningKey::from_bytes(&[7; 32]);
+    let mut own = Node::new(hash_node_name("peer"), addr(7241));
+    own.sign(&key);
+
+    let mut intro = GossipMessage::update(
+        MessageId {
+            origin: own.id,
+            seq: 0,
+        },
+        &[own.clone()],
+        Some(1),
+    );
+    intro.sign(&key);
+    let mut msg = GossipMessage::app(
+        MessageId {
+            origin: own.id,
+            seq: 1,
+        },
+        1,
+        &"x".repeat(10_000),
+        Some(1),
+    )
+    .unwrap();
+    msg.sign(&key);
+
+    let mut stream = peer.connect_stream(addr(7242)).await.unwrap();
+    for frame in [&intro, &msg] {
+        let buf = GossipMessage::serialize(frame).unwrap();
+        write_frame(stream.as_mut(), &buf).await.unwrap();
+    }
+
+    let from = timeout(Duration::from_secs(1), rx.recv())
+        .await
+        .expect("handler did not run")
+        .unwrap();
+    assert_eq!(from.id, own.id);
+    assert_eq!(from.addr, addr(7241));
+    assert!(b.peers().await.iter().any(|n| n.id == own.id));
+}
+
+#[tokio::test]
+async fn restarted_node_messages_are_not_taken_for_duplicates() {
+    let network = MemoryNetwork::with_seed(101);
+    let a_config = GossipConfig {
+        signing_key: Some(SigningKey::from_bytes(&[8; 32])),
+        ..config("node-a", 7251)
+    };
+    let a = spawn_with_config(&network, a_config.clone(), &[]).await;
+    let b = spawn_node(&network, "node-b", 7252, &[("node-a", 7251)]).await;
+    wait_converged(&[&a, &b]).await;
+
+    let mut messages = b.subscribe();
+    for i in 0..20 {
+        a.broadcast(1, &format!("before {}", i)).await.unwrap();
+    }
+    for _ in 0..20 {
+        timeout(Duration::from_secs(1), messages.recv())
+            .await
+            .unwrap()
+            .unwrap();
+    }
+    a.shutdown().await.unwrap();
+
+    // same name and key, so the same origin in every message ID
+    let a = spawn_with_config(&network, a_config, &[("node-b", 7252)]).await;
+    for i in 0..20 {
+        a.broadcast(1, &format!("after {}", i)).await.unwrap();
+    }
+    for i in 0..20 {
+        let msg = timeout(Duration::from_secs(1), messages.recv())
+            .await
+            .expect("restarted node's message was dropped")
+            .unwrap();
+        assert_eq!(msg.decode::<String>().unwrap(), format!("after {}", i));
+    }
+}
+
+#[tokio::test]
+async fn send_to_reaches_only_the_target() {
+    let network = MemoryNetwork::with_seed(67);
+    let a = spawn_node(&network, "node-a", 7181, &[]).await;
+    let b = spawn_node(&network, "node-b", 7182, &[("node-a", 7181)]).await;
+    let c = spawn_node(&network, "node-c", 7183, &[("node-a", 7181)]).await;
+    wait_converged(&[&a, &b, &c]).await;
+
+    let mut b_messages = b.subscribe();
+    let mut c_messages = c.subscribe();
+    a.send_to(b.local_node().id, 1, "psst").await.unwrap();
+
+    let msg = timeout(Duration::from_secs(1), b_messages.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(msg.decode::<String>().unwrap(), "psst");
+
+    let leaked = timeout(Duration::from_millis(300), c_messages.recv()).await;
+    assert!(leaked.is_err(), "message reached a node it wasn't sent to");
+}
+
+#[tokio::test]
+async fn request_gets_the_responders_reply() {
+    let network = MemoryNetwork::with_seed(71);
+
+    let mut handlers = Handlers::new();
+    handlers.respond(1, |_from: Node, msg: GossipMessage| async move {
+        msg.decode::<u32>().unwrap() * 2
+    });
+    let a = spawn_with_handlers(
+        &network,
+        config("node-a", 7191),
+        &[("node-b", 7192)],
+        handlers,
+    )
+    .await;
+    let b = spawn_node(&network, "node-b", 7192, &[("node-a", 7191)]).await;
+    wait_converged(&[&a, &b]).await;
+
+    let a_id = a.local_node().id;
+    let wait = Duration::from_millis(500);
+    let doubled: u32 = b.request(a_id, 1, &21u32, wait).await.unwrap();
+    assert_eq!(doubled, 42);
+
+    // nothing answers type 2
+    let unanswered = b.request::<_, u32>(a_id, 2, &21u32, wait).await;
+    assert!(matches!(unanswered, Err(GossipError::Timeout(_))));
+
+    let unknown = b
+        .request::<_, u32>(hash_node_name("node-z"), 1, &21u32, wait)
+        .await;
+    assert!(matches!(unknown, Err(GossipError::PeerError(_))));
+}
+
+/// Poll until `handle` knows `count` peers subscribed to `topic`.
+async fn wait_for_subscribers(
+    handle: &GossipHandle,
+    topic: &str,
+    count: usize,
+) {
+    timeout(Duration::from_secs(5), async {
+        while handle.subscribers(topic).await.len() != count {
+            sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("subscriptions did not propagate");
+}
+
+#[tokio::test]
+async fn publish_prefers_topic_subscribers() {
+    let network = MemoryNetwork::with_seed(73);
+    let mut handles = vec![];
+    for (i, port) in (7201..7206).enumerate() {
+        // a single hop to a single peer: only the peer picked first
+        // receives a publication
+        let config = GossipConfig {
+            fanout: 1,
+            message_ttl: 1,
+            ..config(&format!("node-{}", i), port)
+        };
+        handles.push(
+            spawn_with_config(&network, config, &[("node-0", 7201)]).await,
+        );
+    }
+    wait_converged(&handles.iter().collect::<Vec<_>>()).await;
+
+    let mut jobs = handles[3].subscribe_topic("jobs").await.unwrap();
+    let mut other = handles[4].subscribe_topic("other").await.unwrap();
+    wait_for_subscribers(&handles[0], "jobs", 1).await;
+
+    for n in 0..5u32 {
+        handles[0].publish("jobs", &n).await.unwrap();
+
+        let msg = timeout(Duration::from_secs(1), jobs.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(msg.topic, "jobs");
+        assert_eq!(msg.from_id, handles[0].local_node().id);
+        assert_eq!(msg.decode::<u32>().unwrap(), n);
+    }
+
+    let leaked = timeout(Duration::from_millis(300), other.recv()).await;
+    assert!(leaked.is_err(), "publication reached another topic");
+
+    handles[3].unsubscribe_topic("jobs").await.unwrap();
+    assert!(jobs.recv().await.is_err());
+    wait_for_subscribers(&handles[0], "jobs", 0).await;
+}
+
+#[tokio::test]
+async fn publication_is_relayed_by_uninterested_nodes() {
+    let network = MemoryNetwork::with_seed(79);
+    let mut handles = vec![];
+    for (i, port) in (7211..7214).enumerate() {
+        let config = GossipConfig {
+            fanout: 2,
+            ..config(&format!("node-{}", i), port)
+        };
+        handles.push(
+            spawn_with_config(&network, config, &[("node-0", 7211)]).await,
+        );
+    }
+    let [a, b, c] = &handles[..] else {
+        unreachable!()
+    };
+    wait_converged(&[a, b, c]).await;
+
+    let mut jobs = c.subscribe_topic("jobs").await.unwrap();
+    wait_for_subscribers(a, "jobs", 1).await;
+
+    // only node-1, which doesn't subscribe, can reach node-2
+    network.partition(&[addr(7211)], &[addr(7213)]);
+    a.publish("jobs", "build").await.unwrap();
+
+    let msg = timeout(Duration::from_secs(1), jobs.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(msg.decode::<String>().unwrap(), "build");
+}
+
+#[tokio::test]
+async fn topics_are_not_limited_by_the_metadata_budget() {
+    let network = MemoryNetwork::with_seed(107);
+    let a_config = GossipConfig {
+        metadata: BTreeMap::from([("k".to_string(), "v".repeat(100))]),
+        tags: BTreeSet::from(["primary".to_string()]),
+        ..config("node-a", 7271)
+    };
+    let a = spawn_with_config(&network, a_config, &[]).await;
+    let b = spawn_node(&network, "node-b", 7272, &[("node-a", 7271)]).await;
+    wait_converged(&[&a, &b]).await;
+
+    let topics = (0..16)
+        .map(|i| format!("topic-with-a-longer-name-{:02}", i))
+        .collect::<Vec<_>>();
+    let mut receivers = vec![];
+    for topic in &topics {
+        receivers.push(a.subscribe_topic(topic.as_str()).await.unwrap());
+    }
+    for topic in &topics {
+        wait_for_subscribers(&b, topic, 1).await;
+    }
+
+    // a record that would no longer fit a datagram is refused
+    let result = a.subscribe_topic("t".repeat(200)).await;
+    assert!(matches!(result, Err(GossipError::PayloadTooLarge(_))));
+    assert_eq!(a.local_node().topics.len(), topics.len());
+}
+
+#[tokio::test]
+async fn largest_metadata_fits_the_smallest_datagram() {
+    let network = MemoryNetwork::with_seed(83);
+    let key = [9u8; 32];
+
+    let metadata = BTreeMap::from([("k".to_string(), "v".repeat(123))]);
+    let mut record = Node::new(hash_node_name("node-a"), addr(7221));
+    record.metadata = metadata.clone();
+    assert_eq!(record.metadata_size(), MAX_METADATA_SIZE);
+
+    let a_config = GossipConfig {
+        max_datagram_size: MIN_DATAGRAM_SIZE,
+        metadata: metadata.clone(),
+        ..encrypted("node-a", 7221, key)
+    };
+    let b_config = GossipConfig {
+        max_datagram_size: MIN_DATAGRAM_SIZE,
+        ..encrypted("node-b", 7222, key)
+    };
+    let a = spawn_with_config(&network, a_config, &[("node-b", 7222)]).await;
+    let b = spawn_with_config(&network, b_config, &[("node-a", 7221)]).await;
+    wait_converged(&[&a, &b]).await;
+
+    let a_id = a.local_node().id;
+    timeout(Duration::from_secs(2), async {
+        loop {
+            let peers = b.peers().await;
+            if peers.iter().any(|p| p.id == a_id && p.metadata == metadata) {
+                break;
+            }
+            sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("metadata did not propagate");
+
+    let too_large = GossipConfig {
+        metadata: BTreeMap::from([("k".to_string(), "v".repeat(124))]),
+        ..config("node-c", 7223)
+    };
+    let result = start(
+        too_large,
+        Box::new(network.transport(addr(7223))),
+        HashMap::new(),
+        Handlers::new(),
+    )
+    .await;
+    assert!(matches!(result, Err(GossipError::InvalidConfig(_))));
+}
+
+#[tokio::test]
+async fn leaving_node_does_not_refute_its_departure() {
+    let network = MemoryNetwork::with_seed(89);
+    let b = spawn_node(&network, "node-b", 7232, &[]).await;
+    let b_node = b.local_node();
+
+    // a hand-driven peer, so the sync below arrives after the leave
+    let peer = network.transport(addr(7231));
+    let key = SigningKey::from_bytes(&[6; 32]);
+    let mut own = Node::new(hash_node_name("peer"), addr(7231));
+    own.sign(&key);
+
+    let mut hello = GossipMessage::update(
+        MessageId {
+            origin: own.id,
+            seq: 0,
+        },
+        &[own.clone()],
+        None,
+    );
+    hello.sign(&key);
+    let buf = GossipMessage::serialize(&hello).unwrap();
+    peer.write(&buf, addr(7232).to_string()).await.unwrap();
+    timeout(Duration::from_secs(1), async {
+        while b.peers().await.is_empty() {
+            sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("node-b did not add the peer");
+
+    // a sync opened before node-b leaves, answered after
+    let mut stream = peer.connect_stream(addr(7232)).await.unwrap();
+    sleep(Duration::from_millis(50)).await;
+    b.shutdown().await.unwrap();
+
+    let mut left = b_node.clone();
+    left.leave();
+    let state = SyncState {
+        nodes: vec![own.clone(), left],
+        entries: vec![],
+    };
+    let mut sync = GossipMessage::sync(
+        MessageId {
+            origin: own.id,
+            seq: 1,
+        },
+        &state,
+    );
+    sync.sign(&key);
+    let buf = GossipMessage::serialize(&sync).unwrap();
+    write_frame(stream.as_mut(), &buf).await.unwrap();
+    read_frame(stream.as_mut(), MAX_FRAME_SIZE).await.unwrap();
+
+    // a refutation would announce node-b back online
+    let mut buf = vec![0; 1024];
+    let refuted = timeout(Duration::from_millis(500), async {
+        loop {
+            let (len, _) = peer.recv_from(&mut buf).await.unwrap();
+            let msg = GossipMessage::deserialize(&buf[..len]).unwrap();
+            if msg.kind != MessageKind::Update {
+                continue;
+            }
+            if msg.nodes().unwrap().iter().any(|n| {
+                n.id == b_node.id
+                    && n.status == NodeStatus::Online
+                    && n.incarnation > b_node.incarnation
+            }) {
+                break;
+            }
+        }
+    })
+    .await;
+    assert!(refuted.is_err(), "node-b refuted its own departure");
+}
@@ -0,0 +1,89 @@
+use std::time::Duration;
+
+use chrono::Utc;
+use gossip::tailscale::{Device, PeerSelector};
+
+fn device(hostname: &str, tags: &[&str]) -> Device {
+    Device {
+        addresses: vec!["100.64.0.1".to_string()],
+        hostname: hostname.to_string(),
+        name: format!("{}.tailnet.ts.net", hostname),
+        tags: tags.iter().map(|t| t.to_string()).collect(),
+        last_seen: Some(Utc::now()),
+        authorized: true,
+        connected_to_control: true,
+    }
+}
+
+#[test]
+fn selects_by_hostname_prefix() {
+    let selector = PeerSelector {
+        hostname_prefix: Some("ht-".to_string()),
+        ..PeerSelector::default()
+    };
+
+    assert!(selector.matches(&device("ht-brave-otter", &[])));
+    assert!(!selector.matches(&device("laptop", &[])));
+}
+
+#[test]
+fn selects_by_any_tag() {
+    let selector = PeerSelector {
+        tags: vec!["tag:gossip".to_string(), "tag:db".to_string()],
+        ..PeerSelector::default()
+    };
+
+    assert!(selector.matches(&device("a", &["tag:db"])));
+    assert!(!selector.matches(&device("b", &["tag:web"])));
+    assert!(!selector.matches(&device("c", &[])));
+}
+
+#[test]
+fn selects_by_liveness() {
+    let selector = PeerSelector {
+        online_only: true,
+        last_seen_within: Some(Duration::from_secs(60)),
+        ..PeerSelector::default()
+    };
+
+    assert!(selector.matches(&device("a", &[])));
+
+    let offline = Device {
+        connected_to_control: false,
+        ..device("b", &[])
+    };
+    assert!(!selector.matches(&offline));
+
+    let stale = Device {
+        last_seen: Some(Utc::now() - chrono::Duration::minutes(5)),
+        ..device("c", &[])
+    };
+    assert!(!selector.matches(&stale));
+
+    let unauthorized = Device {
+        authorized: false,
+        ..device("d", &[])
+    };
+    assert!(!selector.matches(&unauthorized));
+}
+
+#[test]
+fn reads_the_advertised_port_tag() {
+    assert_eq!(
+        device("a", &["tag:gossip", "tag:gossip-port-7946"]).advertised_port(),
+        Some(7946)
+    );
+    assert_eq!(device("b", &["tag:gossip"]).advertised_port(), None);
+
+    // malformed or out of range ports are ignored
+    assert_eq!(device("c", &["tag:gossip-port-"]).advertised_port(), None);
+    assert_eq!(
+        device("d", &["tag:gossip-port-70000"]).advertised_port(),
+        None
+    );
+    assert_eq!(
+        device("e", &["tag:gossip-port-x", "tag:gossip-port-8000"])
+            .advertised_port(),
+        Some(8000)
+    );
+}
@@ -0,0 +1,230 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use ed25519_dalek::SigningKey;
+use gossip::constants::MAX_FRAME_SIZE;
+use gossip::message::{GossipMessage, MessageId, MessageKind, SyncState};
+use gossip::stream::{read_frame, write_frame};
+use gossip::udp::UdpTransport;
+use gossip::{
+    GossipConfig, GossipError, GossipTransport, Handlers, MembershipEvent,
+    Node, start, util::hash_node_name,
+};
+use tokio::sync::mpsc;
+use tokio::time::{sleep, timeout};
+
+async fn bind(name: &str, ip: &str) -> (GossipConfig, UdpTransport) {
+    let mut config = GossipConfig {
+        node_name: name.to_string(),
+        ip_address: ip.to_string(),
+        gossip_port: 0,
+        heartbeat_interval: Duration::from_millis(50),
+        gossip_interval: Duration::from_millis(100),
+        ..GossipConfig::default()
+    };
+
+    let transport = UdpTransport::bind(&config).await.unwrap();
+    config.gossip_port = transport.local_addr().unwrap().port();
+    (config, transport)
+}
+
+async fn exchange_messages(ip: &str) {
+    let (a_config, a_transport) = bind("node-a", ip).await;
+    let (b_config, b_transport) = bind("node-b", ip).await;
+
+    let a_id = hash_node_name("node-a");
+    let a_addr = a_transport.local_addr().unwrap();
+    let seeds = HashMap::from([(a_id, Node::new(a_id, a_addr))]);
+
+    let a = start(
+        a_config,
+        Box::new(a_transport),
+        HashMap::new(),
+        Handlers::new(),
+    )
+    .await
+    .unwrap();
+    let b = start(b_config, Box::new(b_transport), seeds, Handlers::new())
+        .await
+        .unwrap();
+
+    let mut messages = a.subscribe();
+    timeout(Duration::from_secs(5), async {
+        while a.peers().await.is_empty() {
+            sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("node-a never heard from node-b");
+
+    b.broadcast(1, "over udp").await.unwrap();
+    let msg = timeout(Duration::from_secs(1), messages.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(msg.decode::<String>().unwrap(), "over udp");
+
+    a.shutdown().await.unwrap();
+    b.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn exchanges_messages_over_ipv4() {
+    exchange_messages("127.0.0.1").await;
+}
+
+#[tokio::test]
+async fn exchanges_messages_over_ipv6() {
+    exchange_messages("::1").await;
+}
+
+#[tokio::test]
+async fn streams_connect_over_tcp_on_the_gossip_port() {
+    let (_, a) = bind("node-a", "127.0.0.1").await;
+    let (_, b) = bind("node-b", "127.0.0.1").await;
+    let b_addr = b.local_addr().unwrap();
+
+    let (mut outgoing, incoming) = tokio::join!(
+        async { a.connect_stream(b_addr).await.unwrap() },
+        async { b.accept_stream().await.unwrap() },
+    );
+    let (mut incoming, _) = incoming;
+
+    write_frame(outgoing.as_mut(), b"over tcp").await.unwrap();
+    let frame = read_frame(incoming.as_mut(), 64).await.unwrap();
+    assert_eq!(frame, b"over tcp");
+
+    write_frame(outgoing.as_mut(), &[0; 65]).await.unwrap();
+    let result = read_frame(incoming.as_mut(), 64).await;
+    assert!(matches!(result, Err(GossipError::PayloadTooLarge(65))));
+}
+
+#[tokio::test]
+async fn streamed_sender_is_known_by_its_gossip_port() {
+    let (b_config, b_transport) = bind("node-b", "127.0.0.1").await;
+    let b_addr = b_transport.local_addr().unwrap();
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let mut handlers = Handlers::new();
+    handlers.register(1, move |from: Node, _msg: GossipMessage| {
+        let tx = tx.clone();
+        async move {
+            let _ = tx.send(from);
+        }
+    });
+    let b = start(b_config, Box::new(b_transport), HashMap::new(), handlers)
+        .await
+        .unwrap();
+
+    // a peer node-b has never heard from; its stream comes from an
+    // ephemeral TCP port, not the port it gossips on
+    let (_, peer) = bind("peer", "127.0.0.1").await;
+    let peer_addr = peer.local_addr().unwrap();
+    let key = SigningKey::from_bytes(&[7; 32]);
+    let mut own = Node::new(hash_node_name("peer"), peer_addr);
+    own.sign(&key);
+
+    let mut intro = GossipMessage::update(
+        MessageId {
+            origin: own.id,
+            seq: 0,
+        },
+        &[own.clone()],
+        Some(1),
+    );
+    intro.sign(&key);
+    let mut msg = GossipMessage::app(
+        MessageId {
+            origin: own.id,
+            seq: 1,
+        },
+        1,
+        &"x".repeat(10_000),
+        Some(1),
+    )
+    .unwrap();
+    msg.sign(&key);
+
+    let mut stream = peer.connect_stream(b_addr).await.unwrap();
+    for frame in [&intro, &msg] {
+        let buf = GossipMessage::serialize(frame).unwrap();
+        write_frame(stream.as_mut(), &buf).await.unwrap();
+    }
+
+    let from = timeout(Duration::from_secs(1), rx.recv())
+        .await
+        .expect("handler did not run")
+        .unwrap();
+    assert_eq!(from.addr, peer_addr);
+
+    // so what node-b sends it arrives on its gossip socket
+    b.send_to(own.id, 2, "reply").await.unwrap();
+    let mut buf = vec![0; 2048];
+    timeout(Duration::from_secs(1), async {
+        loop {
+            let (len, _) = peer.recv_from(&mut buf).await.unwrap();
+            let msg = GossipMessage::deserialize(&buf[..len]).unwrap();
+            if msg.kind == MessageKind::Application(2) {
+                break;
+            }
+        }
+    })
+    .await
+    .expect("node-b's message never reached the gossip port");
+
+    b.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn sync_partner_joins_at_its_gossip_port() {
+    let (b_config, b_transport) = bind("node-b", "127.0.0.1").await;
+    let b_addr = b_transport.local_addr().unwrap();
+    let b = start(
+        b_config,
+        Box::new(b_transport),
+        HashMap::new(),
+        Handlers::new(),
+    )
+    .await
+    .unwrap();
+    let mut events = b.events();
+
+    // a peer node-b has never heard from opens a push-pull sync
+    let (_, peer) = bind("peer", "127.0.0.1").await;
+    let peer_addr = peer.local_addr().unwrap();
+    let key = SigningKey::from_bytes(&[8; 32]);
+    let mut own = Node::new(hash_node_name("peer"), peer_addr);
+    own.sign(&key);
+
+    let state = SyncState {
+        nodes: vec![own.clone()],
+        entries: vec![],
+    };
+    let mut sync = GossipMessage::sync(
+        MessageId {
+            origin: own.id,
+            seq: 0,
+        },
+        &state,
+    );
+    sync.sign(&key);
+
+    let mut stream = peer.connect_stream(b_addr).await.unwrap();
+    let buf = GossipMessage::serialize(&sync).unwrap();
+    write_frame(stream.as_mut(), &buf).await.unwrap();
+    read_frame(stream.as_mut(), MAX_FRAME_SIZE).await.unwrap();
+
+    let joined = timeout(Duration::from_secs(1), async {
+        loop {
+            if let Ok(MembershipEvent::Joined(node)) = events.recv().await
+                && node.id == own.id
+            {
+                break node;
+            }
+        }
+    })
+    .await
+    .expect("peer never joined");
+    assert_eq!(joined.addr, peer_addr);
+
+    b.shutdown().await.unwrap();
+}
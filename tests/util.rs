@@ -0,0 +1,39 @@
+use std::collections::HashSet;
+
+use gossip::NodeId;
+use gossip::util::hash_node_name;
+
+#[test]
+fn node_ids_match_truncated_sha256_vectors() {
+    // published SHA-256 test vectors cut to 128 bits; IDs must never
+    // change between releases or nodes would stop recognising each other
+    assert_eq!(
+        hash_node_name(""),
+        NodeId(0xe3b0c44298fc1c149afbf4c8996fb924)
+    );
+    assert_eq!(
+        hash_node_name("abc"),
+        NodeId(0xba7816bf8f01cfea414140de5dae2223)
+    );
+    assert_eq!(
+        hash_node_name(
+            "abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"
+        ),
+        NodeId(0x248d6a61d20638b8e5c026930c3e6039)
+    );
+}
+
+#[test]
+fn distinct_names_get_distinct_ids() {
+    let names = (0..10_000)
+        .map(|i| format!("node-{}", i))
+        .chain(["node-a".to_string(), "node-b".to_string()])
+        .collect::<Vec<_>>();
+    let ids = names
+        .iter()
+        .map(|n| hash_node_name(n))
+        .collect::<HashSet<_>>();
+
+    assert_eq!(ids.len(), names.len());
+    assert_eq!(hash_node_name("node-a"), hash_node_name("node-a"));
+}